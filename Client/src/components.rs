@@ -4,8 +4,9 @@ use bevy::prelude::*;
 #[derive(Component, Reflect, Default)]
 pub struct BulletReady(pub bool);
 
+/// A bullet, with the `bullet_id` of its row in the server
 #[derive(Component, Reflect, Default)]
-pub struct Bullet;
+pub struct Bullet(pub u64);
 
 #[derive(Component, Resource, Reflect, Default, Clone, Copy)]
 pub struct MoveDir(pub Vec2);
@@ -17,5 +18,12 @@ pub struct MainCamera;
 #[derive(Resource, Default, Clone, Copy, PartialEq, Eq)]
 pub(crate) struct LocalPlayerHandle(pub(crate) PlayerId);
 
+/// The match of the local player, and its `entity_id`
+#[derive(Resource, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CurrentMatch {
+    pub(crate) match_id: u64,
+    pub(crate) entity_id: u64,
+}
+
 #[derive(Resource, Default)]
 pub(crate) struct InterludeTimer(pub(crate) usize);
@@ -1,42 +1,51 @@
 /// Interface to the SpaceTimeDb database engine.
 ///
 use bevy::prelude::*;
-use spacetime_client_sdk::messages::SpaceDbRequest;
-use spacetime_client_sdk::spacetimedb::TypeValue;
-use spacetime_client_sdk::web_socket::{Client, ConnectionHandle};
-use std::sync::Arc;
+use spacetime_client_sdk::async_client::ReducerCall;
+use spacetime_client_sdk::errors::ClientError;
+use spacetime_client_sdk::table::TableRow;
+use spacetime_client_sdk::web_socket::Client;
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
-pub(crate) enum PlayerId {
-    One,
-    Two,
-}
+use crate::module_bindings;
+pub(crate) use crate::module_bindings::{
+    Bullet as BulletRow, Match, PlayerComponent, PositionComponent, RoundResult, Score,
+};
 
-impl Default for PlayerId {
-    fn default() -> Self {
-        Self::One
-    }
-}
+/// The players of a match. With more than 2 players, it's a free-for-all
+pub(crate) const MIN_PLAYERS: u8 = 2;
+pub(crate) const MAX_PLAYERS: u8 = 8;
+
+/// The slot of a player in its match
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct PlayerId(u8);
 
 impl PlayerId {
     pub fn as_idx(&self) -> usize {
-        match self {
-            PlayerId::One => 0,
-            PlayerId::Two => 1,
-        }
+        self.0 as usize
+    }
+
+    /// The player in the `slot` of a match
+    pub fn from_slot(slot: u8) -> Option<Self> {
+        (slot < MAX_PLAYERS).then_some(Self(slot))
     }
 }
 
 #[derive(Debug, Component, PartialEq, Eq)]
 pub(crate) struct Player {
     pub(crate) handle: PlayerId,
+    /// The `entity_id` of its row in the server
+    pub(crate) entity_id: u64,
     // 4-directions + fire fits easily in a single byte
     pub(crate) input: u8,
 }
 
 impl Player {
-    pub fn new(handle: PlayerId) -> Self {
-        Self { handle, input: 0 }
+    pub fn new(handle: PlayerId, entity_id: u64) -> Self {
+        Self {
+            handle,
+            entity_id,
+            input: 0,
+        }
     }
 
     pub fn as_idx(&self) -> usize {
@@ -44,18 +53,85 @@ impl Player {
     }
 }
 
-/// Add the player to the game SpaceTimeDb instance
-pub(crate) fn create_new_player(db: &Arc<Client>, player: PlayerId, _client_id: &ConnectionHandle) {
-    db.send_message(SpaceDbRequest::FunctionCall {
-        name: "create_new_player".to_string(),
-        args: vec![TypeValue::U32(player.as_idx() as u32), TypeValue::U8(0)],
-    });
+/// The reducer calls waiting for the result from the server
+#[derive(Resource, Default)]
+pub(crate) struct ReducerCalls(pub(crate) Vec<ReducerCall>);
+
+impl ReducerCalls {
+    fn push(&mut self, call: Result<ReducerCall, ClientError>) {
+        match call {
+            Ok(call) => self.0.push(call),
+            Err(err) => error!("Fail to call the reducer: {err}"),
+        }
+    }
+}
+
+/// The rows of the table `T` in the cache of the client
+pub(crate) fn rows<T: TableRow>(db: &Client) -> Vec<T> {
+    db.cache().rows::<T>().unwrap_or_else(|err| {
+        error!("Invalid {} row: {err}", T::table_name());
+        Vec::new()
+    })
+}
+
+/// Open a new match for `max_players` in the SpaceTimeDb instance, with the player in it
+pub(crate) fn create_match(db: &Client, calls: &mut ReducerCalls, max_players: u8) {
+    calls.push(module_bindings::create_match(db, max_players));
 }
 
-/// Updates the player state in the SpaceTimeDb instance
-pub(crate) fn move_player(db: &Arc<Client>, player: PlayerId, input: u8) {
-    db.send_message(SpaceDbRequest::FunctionCall {
-        name: "move_player".to_string(),
-        args: vec![TypeValue::U32(player.as_idx() as u32), TypeValue::U8(input)],
+/// Add the player to a free slot of the match
+pub(crate) fn join_match(db: &Client, calls: &mut ReducerCalls, match_id: u64) {
+    calls.push(module_bindings::join_match(db, match_id));
+}
+
+pub(crate) fn leave_match(db: &Client, calls: &mut ReducerCalls) {
+    calls.push(module_bindings::leave_match(db));
+}
+
+/// The match starts when all its players are ready
+pub(crate) fn set_ready(db: &Client, calls: &mut ReducerCalls, ready: bool) {
+    calls.push(module_bindings::set_ready(db, ready));
+}
+
+/// Updates the player state in the SpaceTimeDb instance, with the `input_seq` of the input.
+///
+/// Sent every frame, so the result is not awaited: a rejected input is still reported as a
+/// [spacetime_client_sdk::web_socket::NetworkEvent::Error]
+pub(crate) fn move_player(db: &Client, entity_id: u64, input: u8, input_seq: u32) {
+    if let Err(err) = module_bindings::move_player(db, entity_id, input, input_seq) {
+        error!("Fail to call the reducer: {err}");
+    }
+}
+
+/// Fire a bullet from the player, if it reloaded
+pub(crate) fn fire_bullet(db: &Client, calls: &mut ReducerCalls, entity_id: u64) {
+    calls.push(module_bindings::fire(db, entity_id));
+}
+
+/// Start a new round, once the server ended the last one
+pub(crate) fn start_round(db: &Client, calls: &mut ReducerCalls) {
+    calls.push(module_bindings::start_round(db));
+}
+
+/// Report the reducer calls rejected by the server
+pub(crate) fn check_reducer_calls(mut calls: ResMut<ReducerCalls>) {
+    calls.0.retain_mut(|call| match call.try_result() {
+        None => true,
+        Some(Ok(ev)) => {
+            if !ev.is_committed() {
+                warn!(
+                    "The server rejected `{}` #{}: {:?} {}",
+                    call.reducer(),
+                    call.id(),
+                    ev.outcome(),
+                    ev.message
+                );
+            }
+            false
+        }
+        Some(Err(err)) => {
+            warn!("{err}");
+            false
+        }
     });
 }
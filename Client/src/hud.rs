@@ -0,0 +1,187 @@
+//! The text shown over the game.
+use bevy::prelude::*;
+use spacetime_client_sdk::bevy_plugin::{RowInserted, RowUpdated, SpacetimeDb};
+
+use crate::components::CurrentMatch;
+use crate::database::{rows, Match, Player, PlayerComponent, RoundResult};
+use crate::sprites::ImageAssets;
+
+/// The status of the opponent, eg: when it disconnects
+#[derive(Component)]
+pub(crate) struct OpponentStatus;
+
+/// The matches to join, or the state of the match joined
+#[derive(Component)]
+pub(crate) struct LobbyText;
+
+/// The rounds won by each player of the match
+#[derive(Component)]
+pub(crate) struct Scoreboard;
+
+/// The player that won the match
+#[derive(Component)]
+pub(crate) struct MatchWinner;
+
+fn text(font: &Handle<Font>, font_size: f32, position: UiRect) -> TextBundle {
+    TextBundle::from_section(
+        "",
+        TextStyle {
+            font: font.clone(),
+            font_size,
+            color: Color::WHITE,
+        },
+    )
+    .with_style(Style {
+        position_type: PositionType::Absolute,
+        position,
+        ..default()
+    })
+}
+
+pub(crate) fn spawn_hud(mut commands: Commands, images: Res<ImageAssets>) {
+    let left = |top| UiRect {
+        top: Val::Px(top),
+        left: Val::Px(10.0),
+        ..default()
+    };
+    commands.spawn((text(&images.font_bold, 40.0, left(10.0)), OpponentStatus));
+    commands.spawn((text(&images.font_bold, 40.0, left(60.0)), LobbyText));
+    commands.spawn((text(&images.font_bold, 80.0, left(120.0)), MatchWinner));
+    commands.spawn((
+        text(
+            &images.font_mono,
+            30.0,
+            UiRect {
+                top: Val::Px(10.0),
+                right: Val::Px(10.0),
+                ..default()
+            },
+        ),
+        Scoreboard,
+    ));
+}
+
+pub(crate) fn clear_lobby(mut text_query: Query<&mut Text, With<LobbyText>>) {
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value.clear();
+    }
+}
+
+/// Tell when the opponent leaves the game, and fade its sprite until it's back
+pub(crate) fn show_opponent_status(
+    db: Res<SpacetimeDb>,
+    current: Option<Res<CurrentMatch>>,
+    mut inserted: EventReader<RowInserted<PlayerComponent>>,
+    mut updated: EventReader<RowUpdated<PlayerComponent>>,
+    mut text_query: Query<&mut Text, With<OpponentStatus>>,
+    mut player_query: Query<(&Player, &mut TextureAtlasSprite)>,
+) {
+    let current = match current {
+        Some(x) => *x,
+        None => return, // Not in a match yet
+    };
+    let changed: Vec<_> = inserted
+        .iter()
+        .map(|x| &x.0)
+        .chain(updated.iter().map(|x| &x.new))
+        .filter(|x| x.match_id == current.match_id && x.entity_id != current.entity_id)
+        .collect();
+    if changed.is_empty() {
+        return;
+    }
+
+    // In a free-for-all many opponents can be gone at once
+    let gone: Vec<_> = rows::<PlayerComponent>(&db)
+        .into_iter()
+        .filter(|x| x.match_id == current.match_id && x.entity_id != current.entity_id)
+        .filter(|x| x.abandoned)
+        .map(|x| format!("Player {} disconnected", x.slot + 1))
+        .collect();
+    for mut text in text_query.iter_mut() {
+        set_text(&mut text, gone.join("\n"));
+    }
+    for row in changed {
+        for (player, mut sprite) in player_query.iter_mut() {
+            if player.entity_id == row.entity_id {
+                let alpha = if row.abandoned { 0.4 } else { 1.0 };
+                sprite.color.set_a(alpha);
+            }
+        }
+    }
+}
+
+fn set_text(text: &mut Text, value: String) {
+    // Don't trigger the change detection, that layout the text again
+    if text.sections[0].value != value {
+        text.sections[0].value = value;
+    }
+}
+
+/// Show the rounds won by each player in the current match, and its winner
+pub(crate) fn show_scores(
+    db: Res<SpacetimeDb>,
+    current: Option<Res<CurrentMatch>>,
+    mut rounds: EventReader<RowInserted<RoundResult>>,
+    mut matches: EventReader<RowUpdated<Match>>,
+    mut score_query: Query<&mut Text, (With<Scoreboard>, Without<MatchWinner>)>,
+    mut winner_query: Query<&mut Text, (With<MatchWinner>, Without<Scoreboard>)>,
+) {
+    let changed = rounds.iter().count() + matches.iter().count() > 0;
+    let (score, winner) = match current {
+        Some(current) if changed || current.is_changed() => {
+            match_scores(&db, current.match_id, current.entity_id)
+        }
+        Some(_) => return,
+        None => (String::new(), String::new()),
+    };
+
+    for mut text in score_query.iter_mut() {
+        set_text(&mut text, score.clone());
+    }
+    for mut text in winner_query.iter_mut() {
+        set_text(&mut text, winner.clone());
+    }
+}
+
+/// The scoreboard & winner text of the match
+fn match_scores(db: &SpacetimeDb, match_id: u64, local: u64) -> (String, String) {
+    let game = match rows::<Match>(db)
+        .into_iter()
+        .find(|x| x.match_id == match_id)
+    {
+        Some(game) => game,
+        None => return (String::new(), String::new()),
+    };
+    let rounds: Vec<_> = rows::<RoundResult>(db)
+        .into_iter()
+        .filter(|x| x.match_id == match_id)
+        .collect();
+    let mut players: Vec<_> = rows::<PlayerComponent>(db)
+        .into_iter()
+        .filter(|x| x.match_id == match_id)
+        .collect();
+    players.sort_by_key(|x| x.slot);
+
+    let name = |player: &PlayerComponent| {
+        if player.entity_id == local {
+            "You".to_string()
+        } else {
+            format!("Player {}", player.slot + 1)
+        }
+    };
+    let mut score = format!("Round {}/{}\n", rounds.len() + 1, game.best_of);
+    for player in &players {
+        let wins = rounds
+            .iter()
+            .filter(|x| x.winner_id == player.entity_id)
+            .count();
+        score += &format!("{:<10} {wins}\n", name(player));
+    }
+
+    let winner = players
+        .iter()
+        .find(|x| game.finished && x.entity_id == game.winner_id)
+        .map(|x| format!("{} won the match!", name(x)))
+        .unwrap_or_default();
+    (score, winner)
+}
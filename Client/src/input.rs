@@ -50,6 +50,11 @@ pub(crate) fn direction(animation: Animation, input: u8) -> (Vec2, Animation) {
     (direction.normalize_or_zero(), animation)
 }
 
+/// The normalized direction of the moves in the `input`
+pub(crate) fn move_dir(input: u8) -> Vec2 {
+    direction(Animation::Idle(Facing::Right), input).0
+}
+
 pub fn fire(input: u8) -> bool {
     input & INPUT_FIRE != 0
 }
@@ -1,21 +1,29 @@
 //! A simplified implementation of the classic game (Extreme Violence)[http://www.geocities.ws/simesgreen/ev/index.html].
-use crate::net::wait_for_players;
 use bevy::prelude::*;
 use bevy_asset_loader::prelude::*;
+use spacetime_client_sdk::bevy_plugin::{SpacetimeDb, SpacetimeDbAppExt, SpacetimeDbPlugin};
 
 use crate::sprites::{animate_sprite, ImageAssets};
 
 mod components;
 mod database;
+mod hud;
 mod input;
+mod module_bindings;
 mod net;
 mod player;
+mod prediction;
 mod sprites;
 
 use crate::components::*;
-use crate::database::Player;
+use crate::database::{
+    check_reducer_calls, rows, BulletRow, Match, PlayerComponent, PositionComponent, ReducerCalls,
+    RoundResult,
+};
+use crate::hud::{clear_lobby, show_opponent_status, show_scores, spawn_hud};
 use crate::net::*;
 use crate::player::*;
+use crate::prediction::Prediction;
 
 #[derive(Clone, Eq, PartialEq, Debug, Hash)]
 enum GameState {
@@ -27,8 +35,6 @@ enum GameState {
 
 pub const PLAYER_SIZE: (f64, f64) = (3121.0, 816.0);
 
-const MAP_SIZE: i32 = 1024 * 2;
-
 fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
     commands.spawn((Camera2dBundle::default(), MainCamera));
 
@@ -37,18 +43,30 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
         texture: asset_server.load("images/Background.png"),
         ..Default::default()
     });
-
-    setup_net(commands);
 }
 
 fn reset_interlude_timer(mut timer: ResMut<InterludeTimer>) {
     timer.0 = 60;
 }
 
-fn interlude_timer(mut timer: ResMut<InterludeTimer>, mut state: ResMut<State<GameState>>) {
+fn interlude_timer(
+    db: Res<SpacetimeDb>,
+    current: Res<CurrentMatch>,
+    mut timer: ResMut<InterludeTimer>,
+    mut state: ResMut<State<GameState>>,
+) {
     if timer.0 == 0 {
         dbg!("interlude");
-        state.set(GameState::InGame).unwrap();
+        let finished = rows::<Match>(&db)
+            .iter()
+            .any(|x| x.match_id == current.match_id && x.finished);
+        // Back to the lobby once a player won the match
+        let next = if finished {
+            GameState::Matchmaking
+        } else {
+            GameState::InGame
+        };
+        state.set(next).unwrap();
     } else {
         timer.0 -= 1;
     }
@@ -64,6 +82,8 @@ fn main() {
                 .continue_to_state(GameState::Matchmaking),
         )
         .init_resource::<InterludeTimer>()
+        .init_resource::<ReducerCalls>()
+        .init_resource::<Prediction>()
         .insert_resource(ClearColor(Color::rgb(0.53, 0.53, 0.53)))
         // .insert_resource(bevy::ecs::schedule::ReportExecutionOrderAmbiguities)
         .add_plugins(
@@ -78,7 +98,12 @@ fn main() {
                 })
                 .set(ImagePlugin::default_nearest()),
         )
-        .add_event::<Player>()
+        .add_plugin(SpacetimeDbPlugin::new(new_client()))
+        .add_table::<PlayerComponent>()
+        .add_table::<PositionComponent>()
+        .add_table::<BulletRow>()
+        .add_table::<Match>()
+        .add_table::<RoundResult>()
         .add_system_set(
             SystemSet::on_enter(GameState::Interlude).with_system(reset_interlude_timer),
         )
@@ -89,16 +114,27 @@ fn main() {
                 // .with_system(animate_sprite)
                 .with_system(move_players)
                 .with_system(reload_bullet)
-                .with_system(consume_messages)
-                .with_system(handle_network_events)
-                .with_system(listen_for_events)
+                .with_system(update_players)
+                .with_system(sync_positions.after(move_players))
                 .with_system(fire_bullets.after(move_players).after(reload_bullet))
-                .with_system(move_bullet)
-                .with_system(kill_players.after(move_bullet).after(move_players)),
+                .with_system(spawn_bullets)
+                .with_system(move_bullet.after(spawn_bullets))
+                .with_system(kill_players.after(move_bullet).after(move_players))
+                .with_system(end_round.after(kill_players)),
+        )
+        .add_system_set(
+            SystemSet::on_exit(GameState::AssetLoading)
+                .with_system(setup)
+                .with_system(spawn_hud),
         )
-        .add_system_set(SystemSet::on_enter(GameState::Matchmaking).with_system(setup))
-        .add_system_set(SystemSet::on_update(GameState::Matchmaking).with_system(wait_for_players))
+        .add_system_set(SystemSet::on_enter(GameState::Matchmaking).with_system(leave_game))
+        .add_system_set(SystemSet::on_update(GameState::Matchmaking).with_system(matchmaking))
+        .add_system_set(SystemSet::on_exit(GameState::Matchmaking).with_system(clear_lobby))
         .add_system_set(SystemSet::on_update(GameState::InGame).with_system(camera_follow))
+        .add_system(on_network_events)
+        .add_system(show_opponent_status)
+        .add_system(show_scores)
+        .add_system(check_reducer_calls)
         .add_system(bevy::window::close_on_esc)
         .run();
 }
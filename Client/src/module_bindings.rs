@@ -0,0 +1,153 @@
+// Generated by `cargo run -p spacegen` from the `Server` module. DO NOT EDIT.
+#![allow(dead_code)]
+use spacetime_client_sdk::async_client::ReducerCall;
+use spacetime_client_sdk::errors::ClientError;
+#[allow(unused_imports)]
+use spacetime_client_sdk::spacetimedb::{Hash, TypeValue};
+use spacetime_client_sdk::table::TableRow;
+use spacetime_client_sdk::web_socket::Client;
+
+/// A game, played by the players with its `match_id`
+#[derive(Debug, Clone, PartialEq, TableRow)]
+pub struct Match {
+    #[primary_key]
+    pub match_id: u64,
+    /// All the players are ready, so no one else can join
+    pub started: bool,
+    /// The slots of the match
+    pub max_players: u8,
+    pub best_of: u8,
+    /// A player won the match, the `winner_id`
+    pub finished: bool,
+    pub winner_id: u64,
+}
+
+/// The result of a round of a match
+#[derive(Debug, Clone, PartialEq, TableRow)]
+pub struct RoundResult {
+    #[primary_key]
+    pub round_id: u64,
+    pub match_id: u64,
+    /// The round of the match, starting at 1
+    pub number: u32,
+    /// The player that survived the round
+    pub winner_id: u64,
+}
+
+/// The lifetime score of an identity, over all its matches
+#[derive(Debug, Clone, PartialEq, TableRow)]
+pub struct Score {
+    #[primary_key]
+    pub identity: Hash,
+    /// The matches won
+    pub wins: u32,
+    pub kills: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, TableRow)]
+pub struct PlayerComponent {
+    #[primary_key]
+    pub entity_id: u64,
+    pub owner_id: Hash,
+    pub match_id: u64,
+    /// The place of the player in the match, that decides where it spawns
+    pub slot: u8,
+    /// Waiting in the lobby for the match to start
+    pub ready: bool,
+    /// The input encodes all the moves/fire state of the player
+    pub input: u8,
+    /// The sequence number of the `input`, given by the client
+    pub input_seq: u32,
+    /// The player can fire again after releasing the fire button
+    pub bullet_ready: bool,
+    pub alive: bool,
+    /// The owner is disconnected
+    pub abandoned: bool,
+}
+
+/// The identities connected to the database
+#[derive(Debug, Clone, PartialEq, TableRow)]
+pub struct OnlinePlayer {
+    #[primary_key]
+    pub identity: Hash,
+    pub connected_at: u64,
+}
+
+/// The position of a player, advanced by the server on each `tick`
+#[derive(Debug, Clone, PartialEq, TableRow)]
+pub struct PositionComponent {
+    #[primary_key]
+    pub entity_id: u64,
+    pub x: f32,
+    pub y: f32,
+    /// The direction the player is facing, the last one it moved to
+    pub dir_x: f32,
+    pub dir_y: f32,
+    /// The sequence number of the last input applied, so the client can replay the newer ones
+    pub last_seq: u32,
+}
+
+/// A bullet fired by the player `owner_id`, advanced by the server on each `move_bullets`
+#[derive(Debug, Clone, PartialEq, TableRow)]
+pub struct Bullet {
+    #[primary_key]
+    pub bullet_id: u64,
+    pub owner_id: u64,
+    pub match_id: u64,
+    pub x: f32,
+    pub y: f32,
+    pub dir_x: f32,
+    pub dir_y: f32,
+}
+
+/// Open a new match for `max_players`, and join it
+/// Call the reducer `create_match`
+pub fn create_match(client: &Client, max_players: u8) -> Result<ReducerCall, ClientError> {
+    client.call_reducer("create_match", vec![TypeValue::U8(max_players)])
+}
+
+/// Call the reducer `join_match`
+pub fn join_match(client: &Client, match_id: u64) -> Result<ReducerCall, ClientError> {
+    client.call_reducer("join_match", vec![TypeValue::U64(match_id)])
+}
+
+/// Call the reducer `leave_match`
+pub fn leave_match(client: &Client) -> Result<ReducerCall, ClientError> {
+    client.call_reducer("leave_match", vec![])
+}
+
+/// Start the match once all its slots are taken by ready players
+/// Call the reducer `set_ready`
+pub fn set_ready(client: &Client, ready: bool) -> Result<ReducerCall, ClientError> {
+    client.call_reducer("set_ready", vec![TypeValue::Bool(ready)])
+}
+
+/// Set the `input` of the player, applied by the next `tick`
+/// Call the reducer `move_player`
+pub fn move_player(
+    client: &Client,
+    entity_id: u64,
+    input: u8,
+    input_seq: u32,
+) -> Result<ReducerCall, ClientError> {
+    client.call_reducer(
+        "move_player",
+        vec![
+            TypeValue::U64(entity_id),
+            TypeValue::U8(input),
+            TypeValue::U32(input_seq),
+        ],
+    )
+}
+
+/// Call the reducer `fire`
+pub fn fire(client: &Client, entity_id: u64) -> Result<ReducerCall, ClientError> {
+    client.call_reducer("fire", vec![TypeValue::U64(entity_id)])
+}
+
+/// Revive the players of the caller's match for a new round, once `record_kill` ended the last
+/// one: at most one player is alive. Called by every client, so the late calls are ignored
+/// Call the reducer `start_round`
+pub fn start_round(client: &Client) -> Result<ReducerCall, ClientError> {
+    client.call_reducer("start_round", vec![])
+}
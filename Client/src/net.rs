@@ -1,178 +1,194 @@
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::env;
 
 use bevy::prelude::*;
-use spacetime_client_sdk::messages::SpaceDbResponse;
-use spacetime_client_sdk::web_socket::{Client, ConnectionHandle, NetworkEvent};
+use spacetime_client_sdk::bevy_plugin::{RowInserted, RowUpdated, SpacetimeDb};
+use spacetime_client_sdk::credentials::FileCredentials;
+use spacetime_client_sdk::web_socket::{Client, NetworkEvent};
 
-use crate::components::{InterludeTimer, LocalPlayerHandle};
+use crate::components::{CurrentMatch, InterludeTimer, LocalPlayerHandle};
 use crate::database::*;
-use crate::player::current_player;
+use crate::hud::LobbyText;
 use crate::GameState;
 
-#[derive(Resource, Clone)]
-pub(crate) struct WsClient {
-    pub(crate) client: Arc<Client>,
-    pub(crate) client_id: Option<ConnectionHandle>,
+/// The client of the game SpaceTimeDb instance, connected by the `SpacetimeDbPlugin`
+pub(crate) fn new_client() -> Client {
+    // Each profile keeps its own identity, so many players can run in the same machine
+    let profile = match env::args().nth(1) {
+        Some(profile) => format!("extremeviolenceonspace_{profile}"),
+        None => "extremeviolenceonspace".to_string(),
+    };
+    let credentials = FileCredentials::in_config_dir(&profile)
+        .expect("Fail to find where to save the credentials");
+    // Point the game to another server with eg: `SPACETIMEDB_HOST=wss://spacetime.example.com`
+    let host = env::var("SPACETIMEDB_HOST").unwrap_or_else(|_| "127.0.0.1:3000".into());
+    let database =
+        env::var("SPACETIMEDB_DATABASE").unwrap_or_else(|_| "extremeviolenceonspace".into());
+    Client::new(&host, &database)
+        .expect("Fail to build ws client")
+        .with_credentials(credentials)
 }
 
-#[derive(Resource)]
-pub(crate) struct WsMsg {
-    pub(crate) ev: Vec<Player>,
-}
-
-impl WsMsg {
-    pub fn new() -> Self {
-        Self { ev: Vec::new() }
+/// Subscribe to the game when connected. On reconnection the client restores the subscription
+pub(crate) fn on_network_events(db: Res<SpacetimeDb>, mut events: EventReader<NetworkEvent>) {
+    for ev in events.iter() {
+        match ev {
+            NetworkEvent::Connected(_) => {
+                db.subscribe(vec![
+                    "SELECT * FROM Match".to_string(),
+                    "SELECT * FROM PlayerComponent".to_string(),
+                    "SELECT * FROM PositionComponent".to_string(),
+                    "SELECT * FROM Bullet".to_string(),
+                    "SELECT * FROM RoundResult".to_string(),
+                    "SELECT * FROM Score".to_string(),
+                ]);
+            }
+            NetworkEvent::Reconnected(_) => {
+                info!("Reconnected to SpaceTimeDb");
+            }
+            NetworkEvent::Message(_, _) => {}
+            NetworkEvent::Disconnected(_) => {
+                warn!("Disconnected from SpaceTimeDb, reconnecting...");
+            }
+            NetworkEvent::Error(client_id, err) => {
+                error!("Get a error from the server for {client_id:?}: {err}");
+            }
+        }
     }
 }
 
-pub(crate) fn setup_net(mut commands: Commands) {
-    let mut client =
-        Client::new("127.0.0.1:3000", "extremeviolenceonspace").expect("Fail to build ws client");
-    client.connect().expect("Fail to connect to SpaceTimeDb");
-
-    commands.insert_resource(WsClient {
-        client: Arc::new(client),
-        client_id: None,
-    });
-
-    let network_events = WsMsg::new();
-    commands.insert_resource(network_events);
-}
-
-#[derive(Resource)]
-struct MsgRec {
-    socket: WsClient,
-}
-
-pub(crate) fn wait_for_players(
+const JOIN_KEYS: [KeyCode; 9] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+/// List the open matches to join one, and go in-game once the match of the player starts
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn matchmaking(
     mut commands: Commands,
-    mut socket: ResMut<WsClient>,
+    db: Res<SpacetimeDb>,
+    keys: Res<Input<KeyCode>>,
+    mut size: Local<u8>,
+    mut calls: ResMut<ReducerCalls>,
     mut state: ResMut<State<GameState>>,
     mut interlude_timer: ResMut<InterludeTimer>,
+    mut text_query: Query<&mut Text, With<LobbyText>>,
 ) {
-    if !socket.client.is_running() {
-        return;
-    }
-    let mut clients = HashMap::with_capacity(2);
-
-    if let Some(msg) = socket.client.try_recv() {
-        match msg {
-            NetworkEvent::Connected(client_id) => {
-                socket.client_id = Some(client_id.clone());
-                create_new_player(&socket.client, PlayerId::One, &client_id);
-                create_new_player(&socket.client, PlayerId::Two, &client_id);
+    let identity = match db.identity() {
+        Some(x) => x,
+        None => return, // Not connected yet
+    };
+    let matches = rows::<Match>(&db);
+    let players = rows::<PlayerComponent>(&db);
+    let count = |match_id| players.iter().filter(|x| x.match_id == match_id).count();
+
+    let lobby = match players.iter().find(|x| identity.is(&x.owner_id)) {
+        // Pick a match
+        None => {
+            let open: Vec<_> = matches
+                .iter()
+                .filter(|x| !x.started && count(x.match_id) < x.max_players as usize)
+                .take(JOIN_KEYS.len())
+                .collect();
+            // The players of a new match
+            *size = size.clamp(MIN_PLAYERS, MAX_PLAYERS);
+            if keys.just_pressed(KeyCode::Left) {
+                *size = (*size - 1).max(MIN_PLAYERS);
             }
-            NetworkEvent::Message(ref client_id, msg) => {
-                warn!("Get {msg:?}");
-                if let SpaceDbResponse::SubscriptionUpdate(table) = msg {
-                    for x in table.table_updates {
-                        if x.table_name == "PlayerComponent" {
-                            info!("Inserting players...");
-                            for row in x.table_row_operations {
-                                let player_id = *row.row[0].as_i8().unwrap();
-                                let player = match player_id {
-                                    0 => PlayerId::One,
-                                    1 => PlayerId::Two,
-                                    x => panic!("Invalid PlayerId {x}"),
-                                };
-                                info!("Row player {:?} {:?}", &player, &row);
-                                clients.insert(player, client_id.clone());
-                            }
-                        }
-                    }
-                }
+            if keys.just_pressed(KeyCode::Right) {
+                *size = (*size + 1).min(MAX_PLAYERS);
             }
-            NetworkEvent::Disconnected(_) => {
-                socket.client_id = None;
-                return;
+            if keys.just_pressed(KeyCode::N) {
+                create_match(&db, &mut calls, *size);
             }
-            NetworkEvent::Error(client_id, err) => {
-                panic!("Get a error from the server for {client_id:?}: {err}");
+            for (key, game) in JOIN_KEYS.iter().zip(&open) {
+                if keys.just_pressed(*key) {
+                    join_match(&db, &mut calls, game.match_id);
+                }
             }
-        };
-    }
-    dbg!(&clients);
-    match clients.len() {
-        0 => {
-            info!("Waiting for Player1");
-            return;
-        }
-        1 => {
-            info!("Waiting for Player2");
-            return;
-        }
-        2 => {
-            info!("Players ready!");
-        }
-        _ => panic!("This is a 2 player-only game!"),
-    }
 
-    let current_player = current_player();
-    for (player_handle, _player_rec) in clients {
-        dbg!(player_handle);
-        if player_handle == current_player {
-            commands.insert_resource(LocalPlayerHandle(player_handle));
+            let score = rows::<Score>(&db)
+                .into_iter()
+                .find(|x| identity.is(&x.identity));
+            let (wins, kills) = score.map_or((0, 0), |x| (x.wins, x.kills));
+            let mut lobby = format!(
+                "Wins: {wins} Kills: {kills}\n\n[N] New match for < {} > players\n",
+                *size
+            );
+            for (i, game) in open.iter().enumerate() {
+                lobby += &format!(
+                    "[{}] Join match {} ({}/{})\n",
+                    i + 1,
+                    game.match_id,
+                    count(game.match_id),
+                    game.max_players
+                );
+            }
+            lobby
         }
-    }
-
-    // move the socket out of the resource (required because GGRS takes ownership of it)
-    let socket = socket.clone();
-    commands.insert_resource(MsgRec { socket });
-    info!("All peers have joined, going in-game");
-    interlude_timer.0 = 3 * 60;
-    state.set(GameState::Interlude).unwrap();
-}
-
-pub(crate) fn consume_messages(ws: Res<WsClient>, mut player_query: Query<&mut Player>) {
-    if !ws.client.is_running() {
-        return;
-    }
-    while let Some(msg) = ws.client.try_recv() {
-        match msg {
-            NetworkEvent::Connected(_) => {}
-            NetworkEvent::Message(_, msg) => {
-                warn!("CONSUME {msg:?}");
-                if let SpaceDbResponse::TransactionUpdate(table) = msg {
-                    for x in table.subscription_update.table_updates {
-                        if x.table_name == "PlayerComponent" {
-                            for row in x.table_row_operations {
-                                let player_id = *row.row[0].as_i8().unwrap();
-                                let input = *row.row[2].as_i8().unwrap();
-                                let player = match player_id {
-                                    0 => PlayerId::One,
-                                    1 => PlayerId::Two,
-                                    x => panic!("Invalid PlayerId {x}"),
-                                };
-                                info!("Move player {:?}: {input} {:?}", &player, &row);
-                                for mut p in player_query.iter_mut() {
-                                    if p.handle == player {
-                                        p.input = input as u8;
-                                    }
-                                }
-                            }
-                        }
-                    }
+        Some(player) => {
+            let game = matches.iter().find(|x| x.match_id == player.match_id);
+            if game.map_or(false, |x| x.finished) {
+                if keys.just_pressed(KeyCode::L) {
+                    leave_match(&db, &mut calls);
                 }
+                format!("Match {} is over\n[L] Leave", player.match_id)
+            } else if game.map_or(false, |x| x.started) {
+                info!("All peers have joined, going in-game");
+                let handle = PlayerId::from_slot(player.slot).unwrap_or_default();
+                commands.insert_resource(LocalPlayerHandle(handle));
+                commands.insert_resource(CurrentMatch {
+                    match_id: player.match_id,
+                    entity_id: player.entity_id,
+                });
+                interlude_timer.0 = 3 * 60;
+                state.set(GameState::Interlude).unwrap();
+                String::new()
+            } else {
+                if keys.just_pressed(KeyCode::R) {
+                    set_ready(&db, &mut calls, !player.ready);
+                }
+                if keys.just_pressed(KeyCode::L) {
+                    leave_match(&db, &mut calls);
+                }
+                format!(
+                    "Match {}: {}/{} players\n[R] {}\n[L] Leave",
+                    player.match_id,
+                    count(player.match_id),
+                    game.map_or(0, |x| x.max_players),
+                    if player.ready { "Not ready" } else { "Ready" },
+                )
             }
-            NetworkEvent::Disconnected(_) => {}
-            NetworkEvent::Error(client_id, err) => {
-                panic!("Get a error from the server for {client_id:?}: {err}");
-            }
-        };
-    }
-}
+        }
+    };
 
-pub(crate) fn handle_network_events(mut events: ResMut<WsMsg>, mut sink: EventWriter<Player>) {
-    for ev in events.ev.drain(..) {
-        dbg!("HN", &ev);
-        sink.send(ev);
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = lobby.clone();
     }
 }
 
-pub(crate) fn listen_for_events(mut evs: EventReader<Player>) {
-    for ev in evs.iter() {
-        info!("received Player : {:?}", ev);
+/// Apply the input of the players changed in the server
+pub(crate) fn update_players(
+    mut inserted: EventReader<RowInserted<PlayerComponent>>,
+    mut updated: EventReader<RowUpdated<PlayerComponent>>,
+    mut player_query: Query<&mut Player>,
+) {
+    let rows = inserted
+        .iter()
+        .map(|x| &x.0)
+        .chain(updated.iter().map(|x| &x.new));
+    for row in rows {
+        for mut p in player_query.iter_mut() {
+            if p.entity_id == row.entity_id {
+                info!("Move player {:?}: {:?}", &p.handle, &row);
+                p.input = row.input;
+            }
+        }
     }
 }
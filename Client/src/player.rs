@@ -1,63 +1,80 @@
-use bevy::math::Vec3Swizzles;
+use std::time::Duration;
+
 use bevy::prelude::*;
-use std::env;
+use spacetime_client_sdk::bevy_plugin::{RowDeleted, RowInserted, RowUpdated, SpacetimeDb};
 
 use crate::components::*;
 use crate::database::*;
-use crate::input::{direction, fire, input};
-use crate::net::WsClient;
-use crate::sprites::{Animation, AnimationTimer, ImageAssets, SpritesheetAnimator};
-use crate::{GameState, MAP_SIZE};
-
-pub(crate) fn current_player() -> PlayerId {
-    env::args()
-        .skip(1)
-        .map(|x| {
-            if x == "two" {
-                PlayerId::Two
-            } else {
-                PlayerId::One
-            }
-        })
-        .next()
-        .unwrap_or(PlayerId::One)
-}
+use crate::input::{direction, fire, input, move_dir};
+use crate::prediction::{step, Prediction, MAX_STEPS, STEP};
+use crate::sprites::{Animation, AnimationTimer, Facing, ImageAssets, SpritesheetAnimator};
+use crate::GameState;
 
-fn spawn_player(commands: &mut Commands, asset: &Res<ImageAssets>, player: PlayerId) {
-    let (img, pos, move_dir) = match player {
-        PlayerId::One => (&asset.cowboy, Vec3::new(200., 0., 100.), Vec2::X),
-        PlayerId::Two => (&asset.alien, Vec3::new(-200., 0., 100.), -Vec2::X),
+fn spawn_player(
+    commands: &mut Commands,
+    asset: &Res<ImageAssets>,
+    player: PlayerId,
+    entity_id: u64,
+    positions: &[PositionComponent],
+) {
+    let skin = asset.skin(player);
+    let mut transform = Transform::from_xyz(0., 0., 100.);
+    let mut move_dir = MoveDir(Vec2::X);
+    // The server decides where each slot spawns
+    if let Some(row) = positions.iter().find(|x| x.entity_id == entity_id) {
+        place_player(row, &mut transform, &mut move_dir);
+    }
+    let facing = if move_dir.0.x < 0. {
+        Facing::Left
+    } else {
+        Facing::Right
     };
-    let player_animations = SpritesheetAnimator::new(player);
+    let player_animations = SpritesheetAnimator::new(player, facing);
 
     //draw single texture from sprite sheet starting at index 0
     commands
         .spawn(SpriteSheetBundle {
-            transform: Transform {
-                translation: pos,
-                ..Default::default()
-            },
+            transform,
             sprite: TextureAtlasSprite {
                 custom_size: Some(Vec2::new(300., 300.)),
                 index: 0,
+                color: skin.color,
                 ..default()
             },
-            texture_atlas: img.clone(),
+            texture_atlas: skin.atlas,
             ..Default::default()
         })
         .insert(AnimationTimer(Timer::from_seconds(
             0.1,
             TimerMode::Repeating,
         )))
-        .insert(Player::new(player))
+        .insert(Player::new(player, entity_id))
         .insert(player_animations)
         .insert(BulletReady(true))
-        .insert(MoveDir(move_dir));
+        .insert(move_dir);
+}
+
+/// Remove the players & bullets of the last match
+pub(crate) fn leave_game(
+    mut commands: Commands,
+    player_query: Query<Entity, With<Player>>,
+    bullet_query: Query<Entity, With<Bullet>>,
+) {
+    for entity in player_query.iter().chain(bullet_query.iter()) {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<CurrentMatch>();
+    commands.remove_resource::<LocalPlayerHandle>();
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn spawn_players(
     mut commands: Commands,
     asset_server: Res<ImageAssets>,
+    db: Res<SpacetimeDb>,
+    current: Res<CurrentMatch>,
+    mut calls: ResMut<ReducerCalls>,
+    mut prediction: ResMut<Prediction>,
     player_query: Query<Entity, With<Player>>,
     bullet_query: Query<Entity, With<Bullet>>,
 ) {
@@ -68,15 +85,50 @@ pub(crate) fn spawn_players(
         commands.entity(bullet).despawn_recursive();
     }
 
+    // Revive the players killed in the last round, the first time it does nothing
+    start_round(&db, &mut calls);
+    let cache = db.cache();
+    let (players, positions) = match (
+        cache.rows::<PlayerComponent>(),
+        cache.rows::<PositionComponent>(),
+    ) {
+        (Ok(players), Ok(positions)) => (players, positions),
+        (Err(err), _) | (_, Err(err)) => {
+            error!("Invalid player row: {err}");
+            return;
+        }
+    };
+
+    // The inputs of the local player follow the last one applied by the server
+    let last_seq = positions
+        .iter()
+        .find(|x| x.entity_id == current.entity_id)
+        .map_or(0, |x| x.last_seq);
+    prediction.reset(last_seq);
+
     dbg!("spawning players");
-    spawn_player(&mut commands, &asset_server, PlayerId::One);
-    spawn_player(&mut commands, &asset_server, PlayerId::Two);
+    for row in players.iter().filter(|x| x.match_id == current.match_id) {
+        match PlayerId::from_slot(row.slot) {
+            Some(player) => spawn_player(
+                &mut commands,
+                &asset_server,
+                player,
+                row.entity_id,
+                &positions,
+            ),
+            None => warn!("Invalid slot {}", row.slot),
+        }
+    }
 }
 
+/// Send the input of the local player every `STEP`, and predict where it takes it
 pub(crate) fn move_players(
     local_player: Option<Res<LocalPlayerHandle>>,
     keys: Res<Input<KeyCode>>,
-    socket: ResMut<WsClient>,
+    time: Res<Time>,
+    db: Res<SpacetimeDb>,
+    mut prediction: ResMut<Prediction>,
+    mut unsent: Local<Duration>,
     mut player_query: Query<(
         &mut SpritesheetAnimator,
         &mut TextureAtlasSprite,
@@ -92,39 +144,78 @@ pub(crate) fn move_players(
         return;
     };
 
+    // The steps since the last frame, without flooding the server after a long one
+    *unsent += time.delta();
+    let steps = (unsent.as_nanos() / STEP.as_nanos()) as u32;
+    *unsent -= STEP * steps;
+    let steps = steps.min(MAX_STEPS);
+
     for (mut animator, mut sprite, mut transform, mut move_direction, mut player) in
         player_query.iter_mut()
     {
         player.input = if player.handle == local_player.0 {
             let input = input(&keys);
-            move_player(&socket.client, player.handle, input);
+            let dir = move_dir(input);
+            for _ in 0..steps {
+                let seq = prediction.push(input);
+                move_player(&db, player.entity_id, input, seq);
+
+                // Predict the move, until the server reconciles it in `sync_positions`
+                if dir != Vec2::ZERO {
+                    let pos = step(transform.translation.truncate(), dir);
+                    transform.translation.x = pos.x;
+                    transform.translation.y = pos.y;
+                    move_direction.0 = dir;
+                }
+            }
             input
         } else {
             player.input
         };
 
-        let (direction, animation) = direction(animator.animation, player.input);
+        // The server moves the other players, see `sync_positions`
+        let (_, animation) = direction(animator.animation, player.input);
         animator.set_state(animation, &mut sprite);
+    }
+}
 
-        if direction == Vec2::ZERO {
-            continue;
+/// Place the players where the server moved them. The local player is placed where its
+/// inputs not yet applied by the server will take it
+pub(crate) fn sync_positions(
+    local_player: Option<Res<LocalPlayerHandle>>,
+    mut prediction: ResMut<Prediction>,
+    mut inserted: EventReader<RowInserted<PositionComponent>>,
+    mut updated: EventReader<RowUpdated<PositionComponent>>,
+    mut player_query: Query<(&Player, &mut Transform, &mut MoveDir)>,
+) {
+    let local_player = local_player.map(|x| x.0);
+    let rows = inserted
+        .iter()
+        .map(|x| &x.0)
+        .chain(updated.iter().map(|x| &x.new));
+    for row in rows {
+        for (player, mut transform, mut move_dir) in player_query.iter_mut() {
+            if player.entity_id != row.entity_id {
+                continue;
+            }
+            if Some(player.handle) == local_player {
+                let (pos, dir) = prediction.reconcile(row);
+                transform.translation.x = pos.x;
+                transform.translation.y = pos.y;
+                move_dir.0 = dir;
+            } else {
+                place_player(row, &mut transform, &mut move_dir);
+            }
         }
-        //dbg!(animation);
-
-        move_direction.0 = direction;
-
-        let move_speed = 20.13;
-        let move_delta = direction * move_speed;
-
-        let old_pos = transform.translation.xy();
-        let limit = Vec2::splat(MAP_SIZE as f32 / 2. - 0.5);
-        let new_pos = (old_pos + move_delta).clamp(-limit, limit);
-
-        transform.translation.x = new_pos.x;
-        transform.translation.y = new_pos.y;
     }
 }
 
+fn place_player(row: &PositionComponent, transform: &mut Transform, move_dir: &mut MoveDir) {
+    transform.translation.x = row.x;
+    transform.translation.y = row.y;
+    move_dir.0 = Vec2::new(row.dir_x, row.dir_y);
+}
+
 pub(crate) fn reload_bullet(mut query: Query<(&mut BulletReady, &Player)>) {
     for (mut can_fire, player) in query.iter_mut() {
         if !fire(player.input) {
@@ -133,86 +224,125 @@ pub(crate) fn reload_bullet(mut query: Query<(&mut BulletReady, &Player)>) {
     }
 }
 
+/// Ask the server to fire a bullet for the local player, see `spawn_bullets`
 pub(crate) fn fire_bullets(
+    local_player: Option<Res<LocalPlayerHandle>>,
+    db: Res<SpacetimeDb>,
+    mut calls: ResMut<ReducerCalls>,
+    mut player_query: Query<(&Player, &mut BulletReady)>,
+) {
+    let local_player = match local_player {
+        Some(x) => x.0,
+        None => return, // Session hasn't started yet
+    };
+
+    for (player, mut bullet_ready) in player_query.iter_mut() {
+        //dbg!(fire(player.input), bullet_ready.0);
+        if player.handle == local_player && fire(player.input) && bullet_ready.0 {
+            fire_bullet(&db, &mut calls, player.entity_id);
+            bullet_ready.0 = false;
+        }
+    }
+}
+
+/// Show the bullets fired in the server
+pub(crate) fn spawn_bullets(
     mut commands: Commands,
     audio: Res<Audio>,
     images: Res<ImageAssets>,
-    mut player_query: Query<(&Transform, &Player, &mut BulletReady, &MoveDir)>,
+    current: Res<CurrentMatch>,
+    mut inserted: EventReader<RowInserted<BulletRow>>,
+    player_query: Query<&Player>,
 ) {
-    for (transform, player, mut bullet_ready, move_dir) in player_query.iter_mut() {
-        //dbg!(fire(player.input), bullet_ready.0);
-        if fire(player.input) && bullet_ready.0 {
-            let player_pos = transform.translation.xy();
-            let pos = player_pos + move_dir.0 * PLAYER_RADIUS + BULLET_RADIUS;
-            let bullet = if player.handle == PlayerId::One {
-                images.bullet_cowboy.clone()
-            } else {
-                images.bullet_alien.clone()
-            };
-
-            commands.spawn((
-                Bullet,
-                *move_dir,
-                SpriteBundle {
-                    transform: Transform::from_translation(pos.extend(200.))
-                        .with_rotation(Quat::from_rotation_arc_2d(Vec2::X, move_dir.0)),
-                    texture: bullet,
-                    sprite: Sprite {
-                        //Making the bullets smaller, but still extreme!
-                        custom_size: Some(Vec2::new(1920.0 / 20.0, 1080.0 / 20.0)),
-                        ..default()
-                    },
+    let rows = inserted
+        .iter()
+        .map(|x| &x.0)
+        .filter(|x| x.match_id == current.match_id);
+    for row in rows {
+        let dir = Vec2::new(row.dir_x, row.dir_y);
+        let owner = player_query.iter().find(|x| x.entity_id == row.owner_id);
+        let skin = images.skin(owner.map(|x| x.handle).unwrap_or_default());
+
+        commands.spawn((
+            Bullet(row.bullet_id),
+            MoveDir(dir),
+            SpriteBundle {
+                transform: Transform::from_xyz(row.x, row.y, 200.)
+                    .with_rotation(Quat::from_rotation_arc_2d(Vec2::X, dir)),
+                texture: skin.bullet,
+                sprite: Sprite {
+                    color: skin.color,
+                    //Making the bullets smaller, but still extreme!
+                    custom_size: Some(Vec2::new(1920.0 / 20.0, 1080.0 / 20.0)),
                     ..default()
                 },
-            ));
-            audio.play(images.bullet_shoot.clone());
-            bullet_ready.0 = false;
-        }
+                ..default()
+            },
+        ));
+        audio.play(images.bullet_shoot.clone());
     }
 }
 
-pub(crate) fn move_bullet(mut query: Query<(&mut Transform, &MoveDir), With<Bullet>>) {
-    for (mut transform, dir) in query.iter_mut() {
-        let delta = (dir.0 * 35.0).extend(0.);
-        transform.translation += delta;
+/// Place the bullets where the server moved them, and remove the ones that are gone
+pub(crate) fn move_bullet(
+    mut commands: Commands,
+    mut updated: EventReader<RowUpdated<BulletRow>>,
+    mut deleted: EventReader<RowDeleted<BulletRow>>,
+    mut query: Query<(Entity, &Bullet, &mut Transform)>,
+) {
+    for RowUpdated { new, .. } in updated.iter() {
+        for (_, bullet, mut transform) in query.iter_mut() {
+            if bullet.0 == new.bullet_id {
+                transform.translation.x = new.x;
+                transform.translation.y = new.y;
+            }
+        }
+    }
+    for RowDeleted(row) in deleted.iter() {
+        for (entity, bullet, _) in query.iter() {
+            if bullet.0 == row.bullet_id {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
     }
 }
 
-// Very inaccurate. It make it more "realistic"!
-const PLAYER_RADIUS: f32 = 24.0;
-const BULLET_RADIUS: f32 = 0.25;
-
-#[allow(clippy::type_complexity)]
+/// Kill the players hit by a bullet in the server. The round goes on until one is left
 pub(crate) fn kill_players(
     mut commands: Commands,
-    mut state: ResMut<State<GameState>>,
-    mut player_query: Query<
-        (
-            Entity,
-            &mut TextureAtlasSprite,
-            &mut SpritesheetAnimator,
-            &Transform,
-        ),
-        (With<Player>, Without<Bullet>),
-    >,
-    bullet_query: Query<&Transform, With<Bullet>>,
+    mut updated: EventReader<RowUpdated<PlayerComponent>>,
+    mut player_query: Query<(
+        Entity,
+        &Player,
+        &mut TextureAtlasSprite,
+        &mut SpritesheetAnimator,
+    )>,
 ) {
-    for (player, mut sprite, mut animator, player_transform) in &mut player_query {
-        for bullet_transform in bullet_query.iter() {
-            let distance = Vec2::distance(
-                player_transform.translation.xy(),
-                bullet_transform.translation.xy(),
-            );
-            if distance < PLAYER_RADIUS + BULLET_RADIUS {
+    for RowUpdated { old, new } in updated.iter() {
+        if !old.alive || new.alive {
+            continue;
+        }
+        for (entity, player, mut sprite, mut animator) in &mut player_query {
+            if player.entity_id == new.entity_id {
                 let facing = animator.animation.facing();
                 animator.set_state(Animation::Dead(facing), &mut sprite);
-                commands.entity(player).despawn_recursive();
-                let _ = state.set(GameState::Interlude);
+                commands.entity(entity).despawn_recursive();
             }
         }
     }
 }
 
+/// Pause between the rounds, once the server recorded the winner of the round
+pub(crate) fn end_round(
+    current: Res<CurrentMatch>,
+    mut state: ResMut<State<GameState>>,
+    mut inserted: EventReader<RowInserted<RoundResult>>,
+) {
+    if inserted.iter().any(|x| x.0.match_id == current.match_id) {
+        let _ = state.set(GameState::Interlude);
+    }
+}
+
 pub(crate) fn camera_follow(
     player_handle: Option<Res<LocalPlayerHandle>>,
     player_query: Query<(&Player, &Transform)>,
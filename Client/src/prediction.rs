@@ -0,0 +1,162 @@
+//! Client-side prediction of the local player.
+//!
+//! The server moves the player a step on each `tick` with its last input, so the local player
+//! would only move after a round-trip. Instead, each input is applied at once and kept until the
+//! server acknowledges it with the `last_seq` of the position. Then the position is reconciled:
+//! the server one, plus the inputs it hasn't applied yet.
+//!
+//! The inputs are sent every [STEP], the rate of the `tick`, so each one is a step of the player
+//! at any frame rate.
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::database::PositionComponent;
+use crate::input::move_dir;
+
+// Must match the `Server` module
+const MAP_SIZE: f32 = 1024.0 * 2.0;
+const MOVE_SPEED: f32 = 20.13;
+
+/// How often the input of the local player is sent, each one moves it a step. Must match the
+/// rate of the `tick` reducer
+pub(crate) const STEP: Duration = Duration::from_millis(16);
+
+/// The most inputs sent in a frame, the rest of a long one is skipped
+pub(crate) const MAX_STEPS: u32 = 4;
+
+/// Don't keep more inputs than these, eg: if the server stops answering
+const MAX_PENDING: usize = 120;
+
+/// The inputs of the local player not yet applied by the server
+#[derive(Resource, Default)]
+pub(crate) struct Prediction {
+    next_seq: u32,
+    pending: VecDeque<(u32, u8)>,
+}
+
+impl Prediction {
+    /// Keep the `input`, and return its sequence number
+    pub(crate) fn push(&mut self, input: u8) -> u32 {
+        self.next_seq += 1;
+        if self.pending.len() == MAX_PENDING {
+            self.pending.pop_front();
+        }
+        self.pending.push_back((self.next_seq, input));
+        self.next_seq
+    }
+
+    /// Forget the inputs, eg: when the players spawn again. The next ones follow the
+    /// `last_seq` applied by the server
+    pub(crate) fn reset(&mut self, last_seq: u32) {
+        self.next_seq = last_seq;
+        self.pending.clear();
+    }
+
+    /// Drop the inputs applied by the server in `row`, and return where the player is once the
+    /// rest are applied
+    pub(crate) fn reconcile(&mut self, row: &PositionComponent) -> (Vec2, Vec2) {
+        while matches!(self.pending.front(), Some((seq, _)) if *seq <= row.last_seq) {
+            self.pending.pop_front();
+        }
+
+        let mut pos = Vec2::new(row.x, row.y);
+        let mut dir = Vec2::new(row.dir_x, row.dir_y);
+        for (_, input) in &self.pending {
+            let delta = move_dir(*input);
+            if delta != Vec2::ZERO {
+                pos = step(pos, delta);
+                dir = delta;
+            }
+        }
+        (pos, dir)
+    }
+}
+
+/// Move from `pos` to `dir`, like the server does on each tick
+pub(crate) fn step(pos: Vec2, dir: Vec2) -> Vec2 {
+    let limit = Vec2::splat(MAP_SIZE / 2. - 0.5);
+    (pos + dir * MOVE_SPEED).clamp(-limit, limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The `input` bits of the right & up moves
+    const RIGHT: u8 = 1 << 3;
+    const UP: u8 = 1 << 0;
+
+    fn position(x: f32, y: f32, last_seq: u32) -> PositionComponent {
+        PositionComponent {
+            entity_id: 0,
+            x,
+            y,
+            dir_x: 1.0,
+            dir_y: 0.0,
+            last_seq,
+        }
+    }
+
+    #[test]
+    fn test_replay_unacknowledged() {
+        let mut prediction = Prediction::default();
+        assert_eq!(prediction.push(RIGHT), 1);
+        assert_eq!(prediction.push(0), 2);
+        assert_eq!(prediction.push(UP), 3);
+
+        // The server applied the first input
+        let (pos, dir) = prediction.reconcile(&position(MOVE_SPEED, 0.0, 1));
+        assert_eq!(pos, Vec2::new(MOVE_SPEED, MOVE_SPEED));
+        assert_eq!(dir, Vec2::Y);
+        assert_eq!(prediction.pending.len(), 2);
+
+        // And then all of them
+        let (pos, dir) = prediction.reconcile(&position(MOVE_SPEED, MOVE_SPEED, 3));
+        assert_eq!(pos, Vec2::new(MOVE_SPEED, MOVE_SPEED));
+        assert_eq!(dir, Vec2::X);
+        assert!(prediction.pending.is_empty());
+    }
+
+    #[test]
+    fn test_idle_inputs_acknowledged() {
+        let mut prediction = Prediction::default();
+        prediction.push(RIGHT);
+        prediction.push(0);
+        prediction.push(0);
+
+        // The server acknowledges the inputs without a move too
+        let (pos, _) = prediction.reconcile(&position(MOVE_SPEED, 0.0, 3));
+        assert_eq!(pos, Vec2::new(MOVE_SPEED, 0.0));
+        assert!(prediction.pending.is_empty());
+    }
+
+    #[test]
+    fn test_max_pending() {
+        let mut prediction = Prediction::default();
+        for _ in 0..MAX_PENDING + 10 {
+            prediction.push(RIGHT);
+        }
+        assert_eq!(prediction.pending.len(), MAX_PENDING);
+        assert_eq!(prediction.pending.front().map(|x| x.0), Some(11));
+
+        // The moves are clamped to the map
+        let (pos, _) = prediction.reconcile(&position(0.0, 0.0, 0));
+        assert_eq!(pos.x, MAP_SIZE / 2. - 0.5);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut prediction = Prediction::default();
+        prediction.push(RIGHT);
+        prediction.push(RIGHT);
+
+        prediction.reset(40);
+        assert_eq!(prediction.push(UP), 41);
+
+        let (pos, _) = prediction.reconcile(&position(5.0, 5.0, 41));
+        assert_eq!(pos, Vec2::new(5.0, 5.0));
+        assert!(prediction.pending.is_empty());
+    }
+}
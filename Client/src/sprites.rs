@@ -19,6 +19,42 @@ pub(crate) struct ImageAssets {
     #[asset(path = "images/CowBoy.png")]
     #[asset(texture_atlas(tile_size_x = 1459.0, tile_size_y = 1920., columns = 5, rows = 1))]
     pub(crate) cowboy: Handle<TextureAtlas>,
+    #[asset(path = "fonts/FiraSans-Bold.ttf")]
+    pub(crate) font_bold: Handle<Font>,
+    #[asset(path = "fonts/FiraMono-Medium.ttf")]
+    pub(crate) font_mono: Handle<Font>,
+}
+
+/// The tint of the players, so the ones with the same sprite sheet can be told apart
+const SKIN_COLORS: [Color; 4] = [
+    Color::WHITE,
+    Color::rgb(1.0, 0.6, 0.6),
+    Color::rgb(0.6, 1.0, 0.6),
+    Color::rgb(0.6, 0.7, 1.0),
+];
+
+/// The look of a player
+pub(crate) struct Skin {
+    pub(crate) atlas: Handle<TextureAtlas>,
+    pub(crate) bullet: Handle<Image>,
+    pub(crate) color: Color,
+}
+
+impl ImageAssets {
+    /// The skin of the player: cowboys & aliens take turns, with a tint per pair
+    pub(crate) fn skin(&self, player: PlayerId) -> Skin {
+        let idx = player.as_idx();
+        let (atlas, bullet) = if idx % 2 == 0 {
+            (&self.cowboy, &self.bullet_cowboy)
+        } else {
+            (&self.alien, &self.bullet_alien)
+        };
+        Skin {
+            atlas: atlas.clone(),
+            bullet: bullet.clone(),
+            color: SKIN_COLORS[idx / 2 % SKIN_COLORS.len()],
+        }
+    }
 }
 
 // A timer for animations
@@ -94,12 +130,7 @@ pub(crate) struct SpritesheetAnimator {
 }
 
 impl SpritesheetAnimator {
-    pub(crate) fn new(player: PlayerId) -> Self {
-        let dir = match player {
-            PlayerId::One => Facing::Left,
-            PlayerId::Two => Facing::Right,
-        };
-
+    pub(crate) fn new(player: PlayerId, dir: Facing) -> Self {
         Self {
             timer: AnimationTimer(Timer::from_seconds(0.6, TimerMode::Repeating)),
             animation: Animation::Idle(dir),
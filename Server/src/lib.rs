@@ -1,4 +1,65 @@
-use spacetimedb::{println, spacetimedb, Hash, ReducerContext};
+use spacetimedb::{println, spacetimedb, Hash, ReducerContext, Timestamp};
+
+const INPUT_UP: u8 = 1 << 0;
+const INPUT_DOWN: u8 = 1 << 1;
+const INPUT_LEFT: u8 = 1 << 2;
+const INPUT_RIGHT: u8 = 1 << 3;
+const INPUT_FIRE: u8 = 1 << 4;
+/// All the valid bits of an input
+const INPUT_ALL: u8 = INPUT_UP | INPUT_DOWN | INPUT_LEFT | INPUT_RIGHT | INPUT_FIRE;
+
+/// The slots of a match. With more than 2 players, it's a free-for-all
+const MIN_PLAYERS: u8 = 2;
+const MAX_PLAYERS: u8 = 8;
+/// The rounds of a match. The first player to win more than half of them wins the match
+const BEST_OF: u8 = 5;
+
+const MAP_SIZE: f32 = 1024.0 * 2.0;
+/// The distance moved by a player on each tick
+const MOVE_SPEED: f32 = 20.13;
+/// The distance moved by a bullet on each tick
+const BULLET_SPEED: f32 = 35.0;
+
+// Very inaccurate. It make it more "realistic"!
+const PLAYER_RADIUS: f32 = 24.0;
+const BULLET_RADIUS: f32 = 0.25;
+
+/// A game, played by the players with its `match_id`
+#[spacetimedb(table)]
+pub struct Match {
+    #[unique]
+    pub match_id: u64,
+    /// All the players are ready, so no one else can join
+    pub started: bool,
+    /// The slots of the match
+    pub max_players: u8,
+    pub best_of: u8,
+    /// A player won the match, the `winner_id`
+    pub finished: bool,
+    pub winner_id: u64,
+}
+
+/// The result of a round of a match
+#[spacetimedb(table)]
+pub struct RoundResult {
+    #[unique]
+    pub round_id: u64,
+    pub match_id: u64,
+    /// The round of the match, starting at 1
+    pub number: u32,
+    /// The player that survived the round
+    pub winner_id: u64,
+}
+
+/// The lifetime score of an identity, over all its matches
+#[spacetimedb(table)]
+pub struct Score {
+    #[unique]
+    pub identity: Hash,
+    /// The matches won
+    pub wins: u32,
+    pub kills: u32,
+}
 
 #[spacetimedb(table)]
 pub struct PlayerComponent {
@@ -6,57 +67,549 @@ pub struct PlayerComponent {
     pub entity_id: u64,
     #[unique]
     pub owner_id: Hash,
+    pub match_id: u64,
+    /// The place of the player in the match, that decides where it spawns
+    pub slot: u8,
+    /// Waiting in the lobby for the match to start
+    pub ready: bool,
     /// The input encodes all the moves/fire state of the player
     pub input: u8,
+    /// The sequence number of the `input`, given by the client
+    pub input_seq: u32,
+    /// The player can fire again after releasing the fire button
+    pub bullet_ready: bool,
+    pub alive: bool,
+    /// The owner is disconnected
+    pub abandoned: bool,
 }
 
-#[spacetimedb(reducer)]
+/// The identities connected to the database
+#[spacetimedb(table)]
+pub struct OnlinePlayer {
+    #[unique]
+    pub identity: Hash,
+    pub connected_at: u64,
+}
 
-pub fn create_new_player(ctx: ReducerContext, entity_id: u64, input: u8) {
-    // Make sure this player doesn't already exist
-    if PlayerComponent::filter_by_entity_id(entity_id).is_some() {
-        println!("A player with this entity_id already exists: {}", entity_id);
+/// The position of a player, advanced by the server on each `tick`
+#[spacetimedb(table)]
+pub struct PositionComponent {
+    #[unique]
+    pub entity_id: u64,
+    pub x: f32,
+    pub y: f32,
+    /// The direction the player is facing, the last one it moved to
+    pub dir_x: f32,
+    pub dir_y: f32,
+    /// The sequence number of the last input applied, so the client can replay the newer ones
+    pub last_seq: u32,
+}
+
+impl PositionComponent {
+    /// Where the player `entity_id` in the `slot` starts: around the center of the map, facing
+    /// it, so the more players the bigger the circle
+    fn spawn(entity_id: u64, slot: u8, max_players: u8) -> Self {
+        let radius = 100.0 * max_players.max(MIN_PLAYERS) as f32;
+        let angle = std::f32::consts::TAU * slot as f32 / max_players.max(1) as f32;
+        let (sin, cos) = angle.sin_cos();
+        Self {
+            entity_id,
+            x: radius * cos,
+            y: radius * sin,
+            dir_x: -cos,
+            dir_y: -sin,
+            last_seq: 0,
+        }
+    }
+}
+
+/// A bullet fired by the player `owner_id`, advanced by the server on each `move_bullets`
+#[spacetimedb(table)]
+pub struct Bullet {
+    #[unique]
+    pub bullet_id: u64,
+    pub owner_id: u64,
+    pub match_id: u64,
+    pub x: f32,
+    pub y: f32,
+    pub dir_x: f32,
+    pub dir_y: f32,
+}
+
+/// The normalized direction of the moves encoded in `input`
+fn direction(input: u8) -> (f32, f32) {
+    let mut x = 0.0;
+    let mut y = 0.0;
+    if input & INPUT_UP != 0 {
+        y += 1.0;
+    }
+    if input & INPUT_DOWN != 0 {
+        y -= 1.0;
+    }
+    if input & INPUT_RIGHT != 0 {
+        x += 1.0;
+    }
+    if input & INPUT_LEFT != 0 {
+        x -= 1.0;
+    }
+    let len = f32::sqrt(x * x + y * y);
+    if len == 0.0 {
+        (0.0, 0.0)
     } else {
-        println!("Creating player with this ID: {}", entity_id);
-        PlayerComponent::insert(PlayerComponent {
+        (x / len, y / len)
+    }
+}
+
+/// Validate the `input` sent by a client
+fn validate(input: u8) -> Result<(), String> {
+    if input & !INPUT_ALL != 0 {
+        return Err(format!("Invalid input {input:#04x}"));
+    }
+    Ok(())
+}
+
+/// The player `entity_id`, if the caller owns it
+fn owned_player(ctx: &ReducerContext, entity_id: u64) -> Result<PlayerComponent, String> {
+    let player = PlayerComponent::filter_by_entity_id(entity_id)
+        .ok_or_else(|| format!("The player {entity_id} doesn't exist"))?;
+    if player.owner_id != ctx.sender {
+        return Err(format!("This identity doesn't own the player {entity_id}"));
+    }
+    Ok(player)
+}
+
+/// The player of the caller
+fn current_player(ctx: &ReducerContext) -> Result<PlayerComponent, String> {
+    PlayerComponent::filter_by_owner_id(ctx.sender)
+        .ok_or_else(|| "This identity isn't in a match".to_string())
+}
+
+fn match_players(match_id: u64) -> impl Iterator<Item = PlayerComponent> {
+    PlayerComponent::iter().filter(move |x| x.match_id == match_id)
+}
+
+/// An id greater than all the `ids`
+fn next_id(ids: impl Iterator<Item = u64>) -> u64 {
+    ids.map(|x| x + 1).max().unwrap_or(0)
+}
+
+/// Put the caller in a free slot of the match `match_id`
+fn add_player(ctx: &ReducerContext, game: &Match) -> Result<(), String> {
+    let match_id = game.match_id;
+    let players: Vec<_> = match_players(match_id).collect();
+    let slot = (0..game.max_players)
+        .find(|slot| players.iter().all(|x| x.slot != *slot))
+        .ok_or_else(|| format!("The match {match_id} is full"))?;
+    let entity_id = next_id(PlayerComponent::iter().map(|x| x.entity_id));
+
+    println!(
+        "Player {} joins the match {} in the slot {}",
+        entity_id, match_id, slot
+    );
+    PlayerComponent::insert(PlayerComponent {
+        entity_id,
+        owner_id: ctx.sender,
+        match_id,
+        slot,
+        ready: false,
+        input: 0,
+        input_seq: 0,
+        bullet_ready: true,
+        alive: true,
+        abandoned: false,
+    });
+    PositionComponent::insert(PositionComponent::spawn(entity_id, slot, game.max_players));
+    Ok(())
+}
+
+/// Remove the player, and its match once empty
+fn remove_player(player: PlayerComponent) {
+    println!(
+        "Player {} leaves the match {}",
+        player.entity_id, player.match_id
+    );
+    PlayerComponent::delete_by_entity_id(player.entity_id);
+    PositionComponent::delete_by_entity_id(player.entity_id);
+
+    if match_players(player.match_id).next().is_none() {
+        Match::delete_by_match_id(player.match_id);
+        for round in RoundResult::iter().filter(|x| x.match_id == player.match_id) {
+            RoundResult::delete_by_round_id(round.round_id);
+        }
+        for bullet in Bullet::iter().filter(|x| x.match_id == player.match_id) {
+            Bullet::delete_by_bullet_id(bullet.bullet_id);
+        }
+    }
+}
+
+/// Revive the players of the match at their spawn points
+fn reset_players(game: &Match) {
+    let match_id = game.match_id;
+    for bullet in Bullet::iter().filter(|x| x.match_id == match_id) {
+        Bullet::delete_by_bullet_id(bullet.bullet_id);
+    }
+    for player in match_players(match_id) {
+        let entity_id = player.entity_id;
+        PositionComponent::update_by_entity_id(
             entity_id,
-            owner_id: ctx.sender,
-            input,
-        });
-        println!("Player created: {}", entity_id);
+            PositionComponent {
+                last_seq: player.input_seq,
+                ..PositionComponent::spawn(entity_id, player.slot, game.max_players)
+            },
+        );
+        PlayerComponent::update_by_entity_id(
+            entity_id,
+            PlayerComponent {
+                bullet_ready: true,
+                alive: true,
+                ..player
+            },
+        );
     }
 }
 
+/// Open a new match for `max_players`, and join it
 #[spacetimedb(reducer)]
-pub fn move_player(ctx: ReducerContext, entity_id: u64, input: u8) {
-    if entity_id > 2 {
-        panic!("This is a 2 player game, so entity_id <= 2");
+pub fn create_match(ctx: ReducerContext, max_players: u8) -> Result<(), String> {
+    if !(MIN_PLAYERS..=MAX_PLAYERS).contains(&max_players) {
+        return Err(format!(
+            "A match is for {MIN_PLAYERS} to {MAX_PLAYERS} players, got {max_players}"
+        ));
     }
+    if current_player(&ctx).is_ok() {
+        return Err("This identity is already in a match".to_string());
+    }
+    let game = Match {
+        match_id: next_id(Match::iter().map(|x| x.match_id)),
+        started: false,
+        max_players,
+        best_of: BEST_OF,
+        finished: false,
+        winner_id: 0,
+    };
+    add_player(&ctx, &game)?;
+    Match::insert(game);
+    Ok(())
+}
 
-    let player =
-        PlayerComponent::filter_by_entity_id(entity_id).expect("This player doesn't exist.");
+#[spacetimedb(reducer)]
+pub fn join_match(ctx: ReducerContext, match_id: u64) -> Result<(), String> {
+    if current_player(&ctx).is_ok() {
+        return Err("This identity is already in a match".to_string());
+    }
+    let game = Match::filter_by_match_id(match_id)
+        .ok_or_else(|| format!("The match {match_id} doesn't exist"))?;
+    if game.started {
+        return Err(format!("The match {match_id} already started"));
+    }
+    add_player(&ctx, &game)
+}
 
-    // Make sure this identity owns this player
-    if player.owner_id != ctx.sender {
-        println!("This identity doesn't own this player! (allowed for now)");
+#[spacetimedb(reducer)]
+pub fn leave_match(ctx: ReducerContext) -> Result<(), String> {
+    remove_player(current_player(&ctx)?);
+    Ok(())
+}
+
+/// Start the match once all its slots are taken by ready players
+#[spacetimedb(reducer)]
+pub fn set_ready(ctx: ReducerContext, ready: bool) -> Result<(), String> {
+    let player = current_player(&ctx)?;
+    let match_id = player.match_id;
+    PlayerComponent::update_by_entity_id(player.entity_id, PlayerComponent { ready, ..player });
+
+    let game = Match::filter_by_match_id(match_id)
+        .ok_or_else(|| format!("The match {match_id} doesn't exist"))?;
+    let players: Vec<_> = match_players(match_id).collect();
+    if players.len() == game.max_players as usize && players.iter().all(|x| x.ready) {
+        println!("Starting the match {}", match_id);
+        reset_players(&game);
+        Match::update_by_match_id(
+            match_id,
+            Match {
+                started: true,
+                ..game
+            },
+        );
     }
+    Ok(())
+}
+
+/// Set the `input` of the player, applied by the next `tick`
+#[spacetimedb(reducer)]
+pub fn move_player(
+    ctx: ReducerContext,
+    entity_id: u64,
+    input: u8,
+    input_seq: u32,
+) -> Result<(), String> {
+    validate(input)?;
+    let player = owned_player(&ctx, entity_id)?;
 
     PlayerComponent::update_by_entity_id(
         entity_id,
         PlayerComponent {
-            entity_id,
-            owner_id: ctx.sender,
             input,
+            input_seq,
+            // Reload once the fire button is released
+            bullet_ready: player.bullet_ready || input & INPUT_FIRE == 0,
+            ..player
+        },
+    );
+    Ok(())
+}
+
+#[spacetimedb(reducer)]
+pub fn fire(ctx: ReducerContext, entity_id: u64) -> Result<(), String> {
+    let player = owned_player(&ctx, entity_id)?;
+    if !player.alive || !player.bullet_ready {
+        println!("The player {} can't fire yet", entity_id);
+        return Ok(());
+    }
+    let pos = PositionComponent::filter_by_entity_id(entity_id)
+        .ok_or_else(|| format!("The player {entity_id} has no position"))?;
+
+    // At the edge of the player, in the direction it faces
+    let offset = PLAYER_RADIUS + BULLET_RADIUS;
+    Bullet::insert(Bullet {
+        bullet_id: next_id(Bullet::iter().map(|x| x.bullet_id)),
+        owner_id: entity_id,
+        match_id: player.match_id,
+        x: pos.x + pos.dir_x * offset,
+        y: pos.y + pos.dir_y * offset,
+        dir_x: pos.dir_x,
+        dir_y: pos.dir_y,
+    });
+    PlayerComponent::update_by_entity_id(
+        entity_id,
+        PlayerComponent {
+            bullet_ready: false,
+            ..player
         },
     );
+    Ok(())
+}
+
+/// Revive the players of the caller's match for a new round, once `record_kill` ended the last
+/// one: at most one player is alive. Called by every client, so the late calls are ignored
+#[spacetimedb(reducer)]
+pub fn start_round(ctx: ReducerContext) -> Result<(), String> {
+    let match_id = current_player(&ctx)?.match_id;
+    let game = match Match::filter_by_match_id(match_id) {
+        Some(game) if !game.finished => game,
+        _ => return Ok(()),
+    };
+    if match_players(match_id).filter(|x| x.alive).count() > 1 {
+        println!("The round of the match {} is not over", match_id);
+        return Ok(());
+    }
+    reset_players(&game);
+    Ok(())
+}
+
+/// Move the players with their last input, so all the clients see the same positions
+#[spacetimedb(reducer, repeat = 16ms)]
+pub fn tick(_ctx: ReducerContext, _prev_time: Timestamp) {
+    let limit = MAP_SIZE / 2.0 - 0.5;
+    for player in PlayerComponent::iter() {
+        let old = match PositionComponent::filter_by_entity_id(player.entity_id) {
+            Some(pos) => pos,
+            None => continue,
+        };
+        let (x, y) = direction(player.input);
+        // Still acknowledge the input of an idle or dead player, so the client stops replaying it
+        if !player.alive || (x == 0.0 && y == 0.0) {
+            if old.last_seq != player.input_seq {
+                PositionComponent::update_by_entity_id(
+                    player.entity_id,
+                    PositionComponent {
+                        last_seq: player.input_seq,
+                        ..old
+                    },
+                );
+            }
+            continue;
+        }
+
+        PositionComponent::update_by_entity_id(
+            player.entity_id,
+            PositionComponent {
+                entity_id: player.entity_id,
+                x: (old.x + x * MOVE_SPEED).clamp(-limit, limit),
+                y: (old.y + y * MOVE_SPEED).clamp(-limit, limit),
+                dir_x: x,
+                dir_y: y,
+                last_seq: player.input_seq,
+            },
+        );
+    }
 }
 
-// #[spacetimedb(disconnect)]
-// pub fn identity_connected(identity: Hash, _timestamp: u64) {
-//     println!("{}", identity);
-// }
-//
-// #[spacetimedb(disconnect)]
-// pub fn identity_disconnected(identity: Hash, _timestamp: u64) {
-//     println!("{}", identity);
-// }
+fn add_score(identity: Hash, wins: u32, kills: u32) {
+    match Score::filter_by_identity(identity) {
+        Some(score) => {
+            Score::update_by_identity(
+                identity,
+                Score {
+                    identity,
+                    wins: score.wins + wins,
+                    kills: score.kills + kills,
+                },
+            );
+        }
+        None => {
+            Score::insert(Score {
+                identity,
+                wins,
+                kills,
+            });
+        }
+    }
+}
+
+/// Kill the `victim` of the player `killer_id`, and end the round when only one player is left.
+///
+/// Only called by `move_bullets`, so the clients can't fake a kill.
+fn record_kill(killer_id: u64, victim: PlayerComponent) {
+    println!("Player {} killed by {}", victim.entity_id, killer_id);
+    let match_id = victim.match_id;
+    PlayerComponent::update_by_entity_id(
+        victim.entity_id,
+        PlayerComponent {
+            alive: false,
+            ..victim
+        },
+    );
+    if let Some(killer) = PlayerComponent::filter_by_entity_id(killer_id) {
+        add_score(killer.owner_id, 0, 1);
+    }
+
+    let mut alive = match_players(match_id).filter(|x| x.alive);
+    let winner = match (alive.next(), alive.next()) {
+        (Some(winner), None) => winner,
+        // The round goes on
+        _ => return,
+    };
+    let game = match Match::filter_by_match_id(match_id) {
+        Some(game) if !game.finished => game,
+        _ => return,
+    };
+
+    let rounds: Vec<_> = RoundResult::iter()
+        .filter(|x| x.match_id == match_id)
+        .collect();
+    RoundResult::insert(RoundResult {
+        round_id: next_id(RoundResult::iter().map(|x| x.round_id)),
+        match_id,
+        number: rounds.len() as u32 + 1,
+        winner_id: winner.entity_id,
+    });
+    println!(
+        "Player {} wins the round {}",
+        winner.entity_id,
+        rounds.len() + 1
+    );
+
+    let wins = 1 + rounds
+        .iter()
+        .filter(|x| x.winner_id == winner.entity_id)
+        .count();
+    if wins > game.best_of as usize / 2 {
+        println!("Player {} wins the match {}", winner.entity_id, match_id);
+        add_score(winner.owner_id, 1, 0);
+        Match::update_by_match_id(
+            match_id,
+            Match {
+                finished: true,
+                winner_id: winner.entity_id,
+                ..game
+            },
+        );
+    }
+}
+
+/// The bullet at `x`,`y` hits the `player`
+fn is_hit(player: &PlayerComponent, x: f32, y: f32) -> bool {
+    match PositionComponent::filter_by_entity_id(player.entity_id) {
+        Some(pos) => {
+            let distance = f32::sqrt((pos.x - x).powi(2) + (pos.y - y).powi(2));
+            distance < PLAYER_RADIUS + BULLET_RADIUS
+        }
+        None => false,
+    }
+}
+
+/// Move the bullets, and kill the players they hit
+#[spacetimedb(reducer, repeat = 16ms)]
+pub fn move_bullets(_ctx: ReducerContext, _prev_time: Timestamp) {
+    let limit = MAP_SIZE / 2.0;
+    for bullet in Bullet::iter() {
+        let x = bullet.x + bullet.dir_x * BULLET_SPEED;
+        let y = bullet.y + bullet.dir_y * BULLET_SPEED;
+        if x.abs() > limit || y.abs() > limit {
+            Bullet::delete_by_bullet_id(bullet.bullet_id);
+            continue;
+        }
+
+        let target = match_players(bullet.match_id).find(|player| {
+            player.alive && player.entity_id != bullet.owner_id && is_hit(player, x, y)
+        });
+        match target {
+            Some(player) => {
+                Bullet::delete_by_bullet_id(bullet.bullet_id);
+                record_kill(bullet.owner_id, player);
+            }
+            None => {
+                Bullet::update_by_bullet_id(bullet.bullet_id, Bullet { x, y, ..bullet });
+            }
+        }
+    }
+}
+
+#[spacetimedb(connect)]
+pub fn identity_connected(identity: Hash, timestamp: u64) {
+    println!("Connected: {}", identity);
+    if OnlinePlayer::filter_by_identity(identity).is_none() {
+        OnlinePlayer::insert(OnlinePlayer {
+            identity,
+            connected_at: timestamp,
+        });
+    }
+
+    // Take back the player left on the last disconnection
+    if let Some(player) = PlayerComponent::filter_by_owner_id(identity) {
+        PlayerComponent::update_by_entity_id(
+            player.entity_id,
+            PlayerComponent {
+                abandoned: false,
+                ..player
+            },
+        );
+    }
+}
+
+#[spacetimedb(disconnect)]
+pub fn identity_disconnected(identity: Hash, _timestamp: u64) {
+    println!("Disconnected: {}", identity);
+    OnlinePlayer::delete_by_identity(identity);
+
+    let player = match PlayerComponent::filter_by_owner_id(identity) {
+        Some(player) => player,
+        None => return,
+    };
+    let started = Match::filter_by_match_id(player.match_id).map_or(false, |x| x.started);
+    if !started {
+        // Free the slot for someone else
+        remove_player(player);
+    } else {
+        // Stop the player, so it doesn't keep running with the last input
+        PlayerComponent::update_by_entity_id(
+            player.entity_id,
+            PlayerComponent {
+                input: 0,
+                abandoned: true,
+                ..player
+            },
+        );
+    }
+}
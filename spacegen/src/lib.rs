@@ -0,0 +1,233 @@
+//! Generate typed client bindings from the `#[spacetimedb(table)]` and
+//! `#[spacetimedb(reducer)]` definitions of a module.
+//!
+//! The tables become structs that `#[derive(TableRow)]`, and the reducers become functions
+//! that call them with the arguments in the right order & type, so a mismatch with the module
+//! is a compile error in the client.
+use quote::ToTokens;
+use std::fmt::Write;
+use syn::punctuated::Punctuated;
+use syn::{Attribute, FnArg, Item, ItemFn, ItemStruct, Meta, Pat, Token, Type};
+
+const HEADER: &str = "\
+// Generated by `cargo run -p spacegen` from the `Server` module. DO NOT EDIT.
+#![allow(dead_code)]
+use spacetime_client_sdk::async_client::ReducerCall;
+use spacetime_client_sdk::errors::ClientError;
+#[allow(unused_imports)]
+use spacetime_client_sdk::spacetimedb::{Hash, TypeValue};
+use spacetime_client_sdk::table::TableRow;
+use spacetime_client_sdk::web_socket::Client;
+";
+
+/// What a `#[spacetimedb(..)]` attribute declares
+#[derive(Debug, PartialEq, Eq)]
+enum Kind {
+    Table,
+    Reducer,
+    /// A reducer called by the database on a schedule, not by the clients
+    Scheduled,
+    Other,
+}
+
+fn kind(attrs: &[Attribute]) -> syn::Result<Option<Kind>> {
+    let attr = match attrs.iter().find(|x| x.path.is_ident("spacetimedb")) {
+        Some(attr) => attr,
+        None => return Ok(None),
+    };
+    let args = attr.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)?;
+    let mut args = args.iter();
+
+    Ok(Some(match args.next() {
+        Some(x) if x.path().is_ident("table") => Kind::Table,
+        Some(x) if x.path().is_ident("reducer") => {
+            if args.any(|x| x.path().is_ident("repeat")) {
+                Kind::Scheduled
+            } else {
+                Kind::Reducer
+            }
+        }
+        _ => Kind::Other,
+    }))
+}
+
+fn has_attr(attrs: &[Attribute], name: &str) -> bool {
+    attrs.iter().any(|x| x.path.is_ident(name))
+}
+
+fn docs(attrs: &[Attribute], indent: &str) -> String {
+    let mut out = String::new();
+    for attr in attrs.iter().filter(|x| x.path.is_ident("doc")) {
+        if let Ok(Meta::NameValue(doc)) = attr.parse_meta() {
+            if let syn::Lit::Str(doc) = doc.lit {
+                writeln!(out, "{indent}///{}", doc.value()).unwrap();
+            }
+        }
+    }
+    out
+}
+
+fn type_name(ty: &Type) -> String {
+    ty.to_token_stream().to_string().replace(' ', "")
+}
+
+/// The [TypeValue] variant that holds a value of the Rust type `ty`
+fn type_value(ty: &Type) -> syn::Result<&'static str> {
+    Ok(match type_name(ty).as_str() {
+        "bool" => "Bool",
+        "i8" => "I8",
+        "u8" => "U8",
+        "i16" => "I16",
+        "u16" => "U16",
+        "i32" => "I32",
+        "u32" => "U32",
+        "i64" => "I64",
+        "u64" => "U64",
+        "i128" => "I128",
+        "u128" => "U128",
+        "String" => "String",
+        "Vec<u8>" => "Bytes",
+        _ => {
+            return Err(syn::Error::new_spanned(
+                ty,
+                "Unsupported type for a reducer argument",
+            ))
+        }
+    })
+}
+
+fn table(out: &mut String, item: &ItemStruct) {
+    // The explicit primary key, or else the first unique column
+    let primary_key = item
+        .fields
+        .iter()
+        .find(|x| has_attr(&x.attrs, "primarykey"))
+        .or_else(|| item.fields.iter().find(|x| has_attr(&x.attrs, "unique")))
+        .and_then(|x| x.ident.as_ref());
+
+    out.push('\n');
+    out.push_str(&docs(&item.attrs, ""));
+    writeln!(out, "#[derive(Debug, Clone, PartialEq, TableRow)]").unwrap();
+    writeln!(out, "pub struct {} {{", item.ident).unwrap();
+    for field in &item.fields {
+        let name = field.ident.as_ref().unwrap();
+        out.push_str(&docs(&field.attrs, "    "));
+        if Some(name) == primary_key {
+            writeln!(out, "    #[primary_key]").unwrap();
+        }
+        writeln!(out, "    pub {name}: {},", type_name(&field.ty)).unwrap();
+    }
+    writeln!(out, "}}").unwrap();
+}
+
+fn reducer(out: &mut String, item: &ItemFn) -> syn::Result<()> {
+    let name = &item.sig.ident;
+    let mut args = Vec::new();
+
+    for arg in &item.sig.inputs {
+        if let FnArg::Typed(arg) = arg {
+            // Supplied by the database
+            if type_name(&arg.ty) == "ReducerContext" {
+                continue;
+            }
+            let ident = match &*arg.pat {
+                Pat::Ident(x) => x.ident.to_string(),
+                x => return Err(syn::Error::new_spanned(x, "Expected an argument name")),
+            };
+            args.push((
+                ident.trim_start_matches('_').to_string(),
+                type_name(&arg.ty),
+                type_value(&arg.ty)?,
+            ));
+        }
+    }
+
+    out.push('\n');
+    out.push_str(&docs(&item.attrs, ""));
+    writeln!(out, "/// Call the reducer `{name}`").unwrap();
+    write!(out, "pub fn {name}(client: &Client").unwrap();
+    for (arg, ty, _) in &args {
+        write!(out, ", {arg}: {ty}").unwrap();
+    }
+    writeln!(out, ") -> Result<ReducerCall, ClientError> {{").unwrap();
+    write!(out, "    client.call_reducer(\"{name}\", vec![").unwrap();
+    for (i, (arg, _, value)) in args.iter().enumerate() {
+        if i > 0 {
+            write!(out, ", ").unwrap();
+        }
+        write!(out, "TypeValue::{value}({arg})").unwrap();
+    }
+    writeln!(out, "])").unwrap();
+    writeln!(out, "}}").unwrap();
+
+    Ok(())
+}
+
+/// Generate the Rust client bindings of the module source `src`
+pub fn generate(src: &str) -> syn::Result<String> {
+    let file = syn::parse_file(src)?;
+    let mut out = HEADER.to_string();
+
+    for item in &file.items {
+        match item {
+            Item::Struct(x) if kind(&x.attrs)? == Some(Kind::Table) => table(&mut out, x),
+            Item::Fn(x) if kind(&x.attrs)? == Some(Kind::Reducer) => reducer(&mut out, x)?,
+            _ => {}
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODULE: &str = r#"
+        use spacetimedb::{spacetimedb, Hash, ReducerContext};
+
+        #[spacetimedb(table)]
+        pub struct PlayerComponent {
+            #[unique]
+            pub entity_id: u64,
+            pub owner_id: Hash,
+            /// The input
+            pub input: u8,
+        }
+
+        #[spacetimedb(reducer)]
+        pub fn move_player(ctx: ReducerContext, entity_id: u64, _input: u8) {}
+
+        #[spacetimedb(reducer, repeat = 33ms)]
+        pub fn tick(_ctx: ReducerContext, _prev_time: Timestamp) {}
+
+        pub fn helper(entity_id: u64) {}
+    "#;
+
+    #[test]
+    fn test_generate() {
+        let code = generate(MODULE).unwrap();
+
+        assert!(code.contains(
+            "pub struct PlayerComponent {\n    #[primary_key]\n    pub entity_id: u64,\n    pub owner_id: Hash,\n    /// The input\n    pub input: u8,\n}"
+        ));
+        assert!(code.contains(
+            "pub fn move_player(client: &Client, entity_id: u64, input: u8) -> Result<ReducerCall, ClientError> {"
+        ));
+        assert!(code.contains(
+            "client.call_reducer(\"move_player\", vec![TypeValue::U64(entity_id), TypeValue::U8(input)])"
+        ));
+        assert!(!code.contains("fn tick"));
+        assert!(!code.contains("fn helper"));
+        syn::parse_file(&code).unwrap();
+    }
+
+    #[test]
+    fn test_unsupported_arg() {
+        let module = r#"
+            #[spacetimedb(reducer)]
+            pub fn teleport(ctx: ReducerContext, to: (f32, f32)) {}
+        "#;
+        assert!(generate(module).is_err());
+    }
+}
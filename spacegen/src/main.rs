@@ -0,0 +1,44 @@
+//! Generate the client bindings of the `Server` module.
+//!
+//! Usage: `cargo run -p spacegen [MODULE_SRC] [OUT]`, by default reads `Server/src/lib.rs`
+//! and writes `Client/src/module_bindings.rs`, formatted with `rustfmt`.
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::{env, fs};
+
+/// Format `code` with `rustfmt`, so the output is the same as the checked-in bindings
+fn rustfmt(code: &str) -> String {
+    let mut child = Command::new("rustfmt")
+        .args(["--edition", "2021", "--emit", "stdout"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to run rustfmt");
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(code.as_bytes())
+        .expect("Failed to write to rustfmt");
+    let output = child.wait_with_output().expect("Failed to run rustfmt");
+    assert!(output.status.success(), "rustfmt failed");
+
+    String::from_utf8(output.stdout).expect("rustfmt output is not UTF-8")
+}
+
+fn main() {
+    let root = env!("CARGO_MANIFEST_DIR");
+    let mut args = env::args().skip(1);
+    let input = args
+        .next()
+        .unwrap_or_else(|| format!("{root}/../Server/src/lib.rs"));
+    let output = args
+        .next()
+        .unwrap_or_else(|| format!("{root}/../Client/src/module_bindings.rs"));
+
+    let src = fs::read_to_string(&input).expect("Failed to read the module source");
+    let code = spacegen::generate(&src).expect("Failed to generate the bindings");
+    fs::write(&output, rustfmt(&code)).expect("Failed to write the bindings");
+    println!("Generated {output} from {input}");
+}
@@ -0,0 +1,925 @@
+//! An async client, to embed in a tokio application.
+//!
+//! The blocking [crate::web_socket::Client] is layered on top of it.
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::cache::ClientCache;
+use crate::credentials::Credentials;
+use crate::errors::ClientError;
+use crate::messages::{
+    encode_args, process_msg, serialize_msg, EventJson, IdentityTokenJson, SpaceDbRequest,
+    StmtResultJson, TableOp, TableUpdateJson,
+};
+use crate::table::TableRow;
+use crate::web_socket::{ConnectionHandle, NetworkEvent};
+use crate::ws::{build_req, build_sql_req, tls_connector, BuildConnection};
+use anyhow::anyhow;
+use futures::{SinkExt, Stream, StreamExt};
+use log::{error, info, warn};
+use spacetimedb::TypeValue;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot::{self, error::TryRecvError};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::{interval_at, sleep, timeout, Instant, MissedTickBehavior};
+use tokio_tungstenite::{connect_async_tls_with_config, MaybeTlsStream, WebSocketStream};
+use tungstenite::handshake::client::Response;
+use tungstenite::http::StatusCode;
+
+/// The [NetworkEvent]s of an [AsyncClient]
+pub struct EventStream {
+    rx: UnboundedReceiver<NetworkEvent>,
+}
+
+impl Stream for EventStream {
+    type Item = NetworkEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+/// Forget the calls without an event after this, eg: an update the server didn't broadcast
+const CALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The most calls waiting for their event, the oldest are forgotten first
+const MAX_PENDING_CALLS: usize = 256;
+
+/// A reducer call, that resolves to its [EventJson] when the server runs it.
+///
+/// The events don't carry the id of the request, so the calls are matched with the events of
+/// the identity of the client by reducer & arguments, in the order they were sent. If the
+/// connection is lost, or the event doesn't arrive in 30 seconds, the result is unknown &
+/// resolves to an error.
+///
+/// Drop it to not wait for the result, eg: for the input sent every frame. A rejected call is
+/// still reported as a [NetworkEvent::Error].
+#[derive(Debug)]
+pub struct ReducerCall {
+    id: u64,
+    reducer: String,
+    rx: oneshot::Receiver<EventJson>,
+}
+
+impl ReducerCall {
+    /// The id of the request, unique for the client
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn reducer(&self) -> &str {
+        &self.reducer
+    }
+
+    /// Poll the result without blocking, eg: once per frame. `None` while waiting for it
+    pub fn try_result(&mut self) -> Option<Result<EventJson, ClientError>> {
+        match self.rx.try_recv() {
+            Ok(ev) => Some(Ok(ev)),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Closed) => Some(Err(call_lost(&self.reducer))),
+        }
+    }
+}
+
+impl Future for ReducerCall {
+    type Output = Result<EventJson, ClientError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.rx)
+            .poll(cx)
+            .map(|x| x.map_err(|_| call_lost(&self.reducer)))
+    }
+}
+
+fn call_lost(reducer: &str) -> ClientError {
+    ClientError::ChannelClosed(format!(
+        "no result for `{reducer}`, the connection was lost or the event never arrived"
+    ))
+}
+
+/// A [ReducerCall] waiting for its event
+struct PendingCall {
+    reducer: String,
+    /// The arguments, encoded like in the [EventJson] of the call
+    arg_bytes: Vec<u8>,
+    sent: Instant,
+    tx: oneshot::Sender<EventJson>,
+}
+
+impl PendingCall {
+    fn is_expired(&self) -> bool {
+        // Closed when the game dropped the `ReducerCall`
+        self.tx.is_closed() || self.sent.elapsed() > CALL_TIMEOUT
+    }
+}
+
+/// The state shared between the client and the task of its connection
+struct Shared {
+    cache: Mutex<ClientCache>,
+    /// The queries of the last [SpaceDbRequest::Subscribe], sent again when reconnecting
+    queries: Mutex<Option<Vec<String>>>,
+    /// The reducer calls sent to the server, oldest first
+    pending: Mutex<VecDeque<PendingCall>>,
+    next_request: AtomicU64,
+    /// The round-trip time of the last ping
+    latency: Mutex<Option<Duration>>,
+    /// Stop reconnecting, see [AsyncClient::disconnect]
+    shutdown: Notify,
+}
+
+impl Shared {
+    fn new(con: &BuildConnection) -> Self {
+        Self {
+            cache: Mutex::new(ClientCache::new(con.schemas.clone())),
+            queries: Mutex::new(None),
+            pending: Mutex::new(VecDeque::new()),
+            next_request: AtomicU64::new(0),
+            latency: Mutex::new(None),
+            shutdown: Notify::new(),
+        }
+    }
+
+    fn pending(&self) -> MutexGuard<VecDeque<PendingCall>> {
+        self.pending
+            .lock()
+            .expect("failed to lock the pending calls")
+    }
+
+    fn latency(&self) -> MutexGuard<Option<Duration>> {
+        self.latency.lock().expect("failed to lock the latency")
+    }
+
+    /// Forget the calls dropped by the game or that waited too long, so the queue stays small
+    fn expire_calls(pending: &mut VecDeque<PendingCall>) {
+        pending.retain(|x| !x.is_expired());
+        while pending.len() >= MAX_PENDING_CALLS {
+            pending.pop_front();
+        }
+    }
+
+    /// Resolve the oldest pending call of the reducer of `ev` with the same arguments, if it was
+    /// called by this client. Events without arguments match any call of the reducer.
+    ///
+    /// Returns the error to report if the call failed.
+    fn resolve(&self, con: &BuildConnection, ev: &EventJson) -> Option<ClientError> {
+        let identity = &con.auth.as_ref()?.identity;
+        if !ev.caller_identity.eq_ignore_ascii_case(identity) {
+            return None;
+        }
+
+        let call = &ev.function_call;
+        let mut pending = self.pending();
+        let pos = pending.iter().position(|x| {
+            x.reducer == call.reducer
+                && (call.arg_bytes.is_empty() || x.arg_bytes == call.arg_bytes)
+        });
+        if let Some(call) = pos.and_then(|pos| pending.remove(pos)) {
+            // The game is free to drop the call if don't care about the result
+            let _ = call.tx.send(ev.clone());
+        }
+
+        (!ev.is_committed()).then(|| ClientError::ServerRejected {
+            reducer: ev.function_call.reducer.clone(),
+            message: ev.message.clone(),
+        })
+    }
+}
+
+/// A client that runs in the tokio runtime of the caller
+pub struct AsyncClient {
+    handle: Option<JoinHandle<()>>,
+    tx: Option<UnboundedSender<tungstenite::Message>>,
+    con: BuildConnection,
+    shared: Arc<Shared>,
+    credentials: Option<Box<dyn Credentials + Send + Sync>>,
+}
+
+impl AsyncClient {
+    pub fn new(con: BuildConnection) -> Self {
+        AsyncClient {
+            handle: None,
+            tx: None,
+            shared: Arc::new(Shared::new(&con)),
+            con,
+            credentials: None,
+        }
+    }
+
+    /// Reuse the credentials saved in `store`, so the client keep the same identity between
+    /// runs. When there are none, or the server rejects them, the new ones are saved on
+    /// [Self::connect].
+    pub fn with_credentials(mut self, store: impl Credentials + Send + Sync + 'static) -> Self {
+        self.set_credentials(store);
+        self
+    }
+
+    /// Like [Self::with_credentials], for a client already built
+    pub fn set_credentials(&mut self, store: impl Credentials + Send + Sync + 'static) {
+        self.credentials = Some(Box::new(store));
+    }
+
+    /// The identity of the client, known after [Self::connect]
+    pub fn identity(&self) -> Option<&IdentityTokenJson> {
+        self.con.auth.as_ref()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.handle.is_some() && self.tx.is_some()
+    }
+
+    /// The round-trip time to the server, measured with the pings of the
+    /// [crate::ws::HeartbeatPolicy]. `None` until the first pong
+    pub fn latency(&self) -> Option<Duration> {
+        *self.shared.latency()
+    }
+
+    async fn login(&mut self) -> Result<BuildConnection, ClientError> {
+        if let (None, Some(store)) = (&self.con.auth, &self.credentials) {
+            if let Some(auth) = store.load()? {
+                // The saved token could be unknown to the server, eg: after a reset
+                match login(self.con.clone().with_auth(auth)).await {
+                    Ok(con) => {
+                        self.con = con;
+                        return Ok(self.con.clone());
+                    }
+                    Err(err) if is_rejected(&err) => {
+                        warn!("The saved credentials were rejected, login with a new identity");
+                        store.clear()?;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+        // With credentials the server returns the same identity, so skip the round-trip
+        if let Some(auth) = &self.con.auth {
+            info!(
+                "Login to: {} with identity {}",
+                &self.con.url, auth.identity
+            );
+            return Ok(self.con.clone());
+        }
+
+        self.con = login(self.con.clone()).await?;
+        if let (Some(store), Some(auth)) = (&self.credentials, &self.con.auth) {
+            store.save(auth)?;
+        }
+        Ok(self.con.clone())
+    }
+
+    /// Login & spawn the connection to the server in the current tokio runtime.
+    ///
+    /// Returns the stream of events of the connection.
+    pub async fn connect(&mut self) -> Result<EventStream, ClientError> {
+        let con = self.login().await?;
+        let (ev_tx, ev_rx) = unbounded_channel();
+        let (from_handler_tx, from_handler_rx) = unbounded_channel();
+        let shared = self.shared.clone();
+
+        let event_loop = event_loop(con, ev_tx, from_handler_rx, shared);
+        self.handle = Some(tokio::spawn(event_loop));
+        self.tx = Some(from_handler_tx);
+
+        Ok(EventStream { rx: ev_rx })
+    }
+
+    /// Close the connection to the server.
+    ///
+    /// The messages already queued are sent, followed by a close frame, then the
+    /// [EventStream] gets a [NetworkEvent::Disconnected] and ends.
+    pub async fn disconnect(&mut self) {
+        // Once the queue is drained, the connection see it closed
+        self.tx = None;
+        self.shared.shutdown.notify_one();
+        if let Some(handle) = self.handle.take() {
+            if let Err(err) = handle.await {
+                error!("failed to stop the connection: {err}");
+            }
+        }
+    }
+
+    /// Decode the rows of the table `T` in `update`, using the schema registered in the
+    /// [BuildConnection] or the one of `T`
+    pub fn decode<T: TableRow>(
+        &self,
+        update: &TableUpdateJson,
+    ) -> Result<Vec<(TableOp, T)>, ClientError> {
+        match self.con.schemas.get(T::table_name()) {
+            Some(schema) => update.decode(schema),
+            None => update.decode(&T::schema()),
+        }
+    }
+
+    /// The local replica of the subscribed tables, updated before the events are sent to the
+    /// [EventStream]
+    pub fn cache(&self) -> MutexGuard<ClientCache> {
+        self.shared.cache.lock().expect("failed to lock the cache")
+    }
+
+    /// Subscribe to the rows that match the SQL `queries`, replacing the previous subscription.
+    ///
+    /// The server answers with a [crate::messages::SpaceDbResponse::SubscriptionUpdate] with
+    /// the matching rows. The subscription is restored when reconnecting.
+    pub fn subscribe(&self, queries: Vec<String>) {
+        *self
+            .shared
+            .queries
+            .lock()
+            .expect("failed to lock the queries") = Some(queries.clone());
+        self.send_message(SpaceDbRequest::Subscribe { queries });
+    }
+
+    /// Run the SQL `query` against the database, over HTTP, outside of any subscription.
+    ///
+    /// Returns a result for each statement of `query`.
+    pub async fn sql(&self, query: &str) -> Result<Vec<StmtResultJson>, ClientError> {
+        let request = build_sql_req(&self.con, query)?;
+        let https = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        let response = hyper::Client::builder()
+            .build(https)
+            .request(request)
+            .await?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+
+        if !status.is_success() {
+            let msg = String::from_utf8_lossy(&body);
+            return Err(anyhow!("the query failed with {status}: {msg}").into());
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Call the reducer `name` of the module with `args`.
+    ///
+    /// Await the returned [ReducerCall] to know if the call committed or failed, or drop it
+    /// to fire & forget.
+    pub fn call_reducer(
+        &self,
+        name: &str,
+        args: Vec<TypeValue>,
+    ) -> Result<ReducerCall, ClientError> {
+        let arg_bytes = encode_args(&args);
+        let msg = SpaceDbRequest::FunctionCall {
+            name: name.to_string(),
+            args,
+        };
+        let msg = serialize_msg(&self.con, msg)?;
+        let channel = self.tx.as_ref().ok_or_else(|| {
+            ClientError::ChannelClosed("trying to call a reducer with an unconnected client".into())
+        })?;
+
+        // Locked until sent, so the calls are pending in the same order they are sent
+        let mut pending = self.shared.pending();
+        channel.send(msg).map_err(|_| {
+            ClientError::ChannelClosed("the connection to the server is closed".into())
+        })?;
+
+        let (tx, rx) = oneshot::channel();
+        Shared::expire_calls(&mut pending);
+        pending.push_back(PendingCall {
+            reducer: name.to_string(),
+            arg_bytes,
+            sent: Instant::now(),
+            tx,
+        });
+        Ok(ReducerCall {
+            id: self.shared.next_request.fetch_add(1, Ordering::Relaxed),
+            reducer: name.to_string(),
+            rx,
+        })
+    }
+
+    pub fn send_message(&self, msg: SpaceDbRequest) {
+        match serialize_msg(&self.con, msg) {
+            Ok(msg) => self.send_raw_message(msg),
+            Err(err) => error!("failed to serialize message: {err}"),
+        }
+    }
+
+    pub fn send_raw_message(&self, msg: tungstenite::Message) {
+        if let Some(channel) = &self.tx {
+            if let Err(e) = channel.send(msg) {
+                warn!("failed to forward message, sink: {:?}", e);
+            }
+        } else {
+            warn!("trying to send message with an uninitialized client",);
+        }
+    }
+}
+
+/// Open the websocket of `con`, with TLS for `wss://`
+async fn connect(con: &BuildConnection) -> Result<(WsStream, Response), ClientError> {
+    let request = build_req(con).body(())?;
+    let connector = Some(tls_connector());
+    Ok(connect_async_tls_with_config(request, None, connector).await?)
+}
+
+/// Open a websocket just to get the identity & token of the connection.
+///
+/// If `con` already has the credentials the server returns the same identity.
+async fn login(con: BuildConnection) -> Result<BuildConnection, ClientError> {
+    info!("Login to: {}...", &con.url);
+    let (_, response) = connect(&con).await?;
+    info!("Logged into: {} DONE", con.url);
+
+    let token = response
+        .headers()
+        .get("spacetime-identity-token")
+        .map(|x| x.to_str());
+    let identity = response
+        .headers()
+        .get("spacetime-identity")
+        .map(|x| x.to_str());
+
+    match (token, identity) {
+        (Some(Ok(token)), Some(Ok(identity))) => {
+            let t = IdentityTokenJson::new(identity, token);
+            Ok(con.with_auth(t))
+        }
+        _ => {
+            warn!("Response not return auth headers");
+            Err(ClientError::AuthFailed)
+        }
+    }
+}
+
+/// If the server refused the credentials of the connection
+fn is_rejected(err: &ClientError) -> bool {
+    matches!(
+        err,
+        ClientError::Tungstenite(tungstenite::Error::Http(response))
+            if response.status() == StatusCode::UNAUTHORIZED
+    )
+}
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Pump the messages between the websocket and the game until the connection is lost.
+///
+/// Returns `false` if the client was disconnected or dropped, so there is nothing left to do.
+async fn session(
+    con: &BuildConnection,
+    ws_stream: WsStream,
+    ev_tx: &UnboundedSender<NetworkEvent>,
+    from_handler_rx: &mut UnboundedReceiver<tungstenite::Message>,
+    shared: &Shared,
+    resubscribe: bool,
+) -> bool {
+    let (mut write, mut read) = ws_stream.split();
+
+    if resubscribe {
+        let queries = shared
+            .queries
+            .lock()
+            .expect("failed to lock the queries")
+            .clone();
+        if let Some(queries) = queries {
+            let sent = match serialize_msg(con, SpaceDbRequest::Subscribe { queries }) {
+                Ok(msg) => write.send(msg).await.map_err(ClientError::from),
+                Err(err) => Err(err),
+            };
+            if let Err(e) = sent {
+                error!("failed to restore the subscription: {}", e);
+            }
+        }
+    }
+
+    // The first ping after an interval, not as soon as connected
+    let period = con.heartbeat.interval;
+    let mut heartbeat = interval_at(Instant::now() + period, period);
+    heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut last_seen = Instant::now();
+    let started = Instant::now();
+
+    loop {
+        tokio::select! {
+            //Receive messages from the websocket
+            msg = read.next() => {
+                match msg {
+                    Some(Ok(msg)) => {
+                        last_seen = Instant::now();
+                        if let tungstenite::Message::Pong(payload) = &msg {
+                            if let Some(latency) = pong_latency(started, payload) {
+                                *shared.latency() = Some(latency);
+                            }
+                        }
+                        if let Some(msg) = process_msg(con, Ok(msg)) {
+                            let mut rejected = None;
+                            if let NetworkEvent::Message(_, msg) = &msg {
+                                let changes = shared.cache.lock().expect("failed to lock the cache").apply(msg);
+                                // Once unlocked, so the callbacks are free to query the cache
+                                changes.notify();
+                                rejected = msg.event().and_then(|ev| shared.resolve(con, ev));
+                            }
+                            if ev_tx.send(msg).is_err() {
+                                return false;
+                            }
+                            if let Some(err) = rejected {
+                                if ev_tx.send(NetworkEvent::Error(None, err)).is_err() {
+                                    return false;
+                                }
+                            }
+                        }
+                    }
+                    Some(Err(err)) => {
+                        // Disconnected, unless the game is gone
+                        return ev_tx.send(NetworkEvent::Error(None, err.into())).is_ok();
+                    }
+                    None => return true,
+                }
+            }
+            //Receive messages from the game
+            game_msg = from_handler_rx.recv() => {
+                match game_msg {
+                    None => {
+                        info!("Closing the connection to: {}", &con.url);
+                        if let Err(e) = write.close().await {
+                            warn!("failed to close the connection: {}", e);
+                        }
+                        // Wait for the server to acknowledge the close frame
+                        let closed = async { while let Some(Ok(_)) = read.next().await {} };
+                        let _ = timeout(Duration::from_secs(1), closed).await;
+                        return false;
+                    }
+                    Some(ev) => {
+                        if let Err(e) = write.send(ev).await {
+                            error!("failed to send message to server: {}", e);
+                        }
+                    }
+                }
+            }
+            //Ping the server, and drop the connection if it stopped answering
+            _ = heartbeat.tick() => {
+                Shared::expire_calls(&mut shared.pending());
+                let timeout = con.heartbeat.timeout;
+                if last_seen.elapsed() > timeout {
+                    warn!("No response from: {} in {:?}", &con.url, timeout);
+                    let err = ClientError::ChannelClosed(format!("the server did not respond in {timeout:?}"));
+                    return ev_tx.send(NetworkEvent::Error(None, err)).is_ok();
+                }
+                if let Err(e) = write.send(ping(started)).await {
+                    error!("failed to ping the server: {}", e);
+                }
+            }
+        }
+    }
+}
+
+/// A ping with the time it was sent since `started`, the server echoes it in the pong
+fn ping(started: Instant) -> tungstenite::Message {
+    let sent = started.elapsed().as_micros() as u64;
+    tungstenite::Message::Ping(sent.to_be_bytes().to_vec())
+}
+
+/// The round-trip time of the ping of the pong `payload`, so a lost pong doesn't count the
+/// time of the next ping
+fn pong_latency(started: Instant, payload: &[u8]) -> Option<Duration> {
+    let sent = Duration::from_micros(u64::from_be_bytes(payload.try_into().ok()?));
+    started.elapsed().checked_sub(sent)
+}
+
+/// Keep the connection to the server, reconnecting with the [crate::ws::ReconnectPolicy]
+/// of `con` when it is lost.
+async fn event_loop(
+    mut con: BuildConnection,
+    ev_tx: UnboundedSender<NetworkEvent>,
+    mut from_handler_rx: UnboundedReceiver<tungstenite::Message>,
+    shared: Arc<Shared>,
+) {
+    let mut reconnecting = false;
+    let mut attempt = 0;
+
+    loop {
+        info!("Connecting to: {}...", &con.url);
+        let connected = tokio::select! {
+            x = connect(&con) => x,
+            _ = shared.shutdown.notified() => return,
+        };
+        match connected {
+            Ok((ws_stream, _)) => {
+                info!("Connected to: {}...", &con.url);
+                attempt = 0;
+                let handle = ConnectionHandle::new();
+                let ev = if reconnecting {
+                    NetworkEvent::Reconnected(handle.clone())
+                } else {
+                    NetworkEvent::Connected(handle.clone())
+                };
+                if ev_tx.send(ev).is_err() {
+                    return;
+                }
+
+                let session = session(
+                    &con,
+                    ws_stream,
+                    &ev_tx,
+                    &mut from_handler_rx,
+                    &shared,
+                    reconnecting,
+                );
+                let alive = session.await;
+                warn!("Disconnected from: {}", &con.url);
+                // The events of the calls in flight are lost with the connection
+                shared.pending().clear();
+                if ev_tx.send(NetworkEvent::Disconnected(handle)).is_err() || !alive {
+                    return;
+                }
+            }
+            Err(err) => {
+                if ev_tx.send(NetworkEvent::Error(None, err)).is_err() {
+                    return;
+                }
+            }
+        }
+
+        // Wait, then reconnect with the same credentials
+        loop {
+            attempt += 1;
+            let delay = match con.reconnect.delay(attempt) {
+                Some(delay) => delay,
+                None => {
+                    error!("Giving up reconnecting to: {}", &con.url);
+                    return;
+                }
+            };
+            info!("Reconnecting in {delay:?}, attempt {attempt}...");
+            let logged = tokio::select! {
+                _ = sleep(delay) => match &con.auth {
+                    // The server returns the same identity, so skip the round-trip
+                    Some(_) => Ok(con.clone()),
+                    None => login(con.clone()).await,
+                },
+                _ = shared.shutdown.notified() => return,
+            };
+
+            match logged {
+                Ok(x) => {
+                    con = x;
+                    break;
+                }
+                Err(err) => {
+                    if ev_tx.send(NetworkEvent::Error(None, err)).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+        reconnecting = true;
+    }
+}
+
+impl Drop for AsyncClient {
+    fn drop(&mut self) {
+        // The connection is closed in the background, see [AsyncClient::disconnect]
+        self.shared.shutdown.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::credentials::FileCredentials;
+    use crate::messages::FunctionCallJson;
+    use crate::mock::MockServer;
+    use crate::ws::{HeartbeatPolicy, ReconnectPolicy};
+    use tungstenite::http::Uri;
+
+    fn event(caller_identity: &str, reducer: &str, status: &str) -> EventJson {
+        EventJson {
+            timestamp: 0,
+            status: status.to_string(),
+            caller_identity: caller_identity.to_string(),
+            function_call: FunctionCallJson {
+                reducer: reducer.to_string(),
+                arg_bytes: Vec::new(),
+            },
+            message: String::new(),
+            energy_quanta_used: 0,
+            host_execution_duration_micros: 0,
+        }
+    }
+
+    fn pending_call(
+        shared: &Shared,
+        reducer: &str,
+        arg_bytes: Vec<u8>,
+        sent: Instant,
+    ) -> oneshot::Receiver<EventJson> {
+        let (tx, rx) = oneshot::channel();
+        shared.pending().push_back(PendingCall {
+            reducer: reducer.to_string(),
+            arg_bytes,
+            sent,
+            tx,
+        });
+        rx
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_latency() {
+        let server = MockServer::start().await.unwrap();
+        let con =
+            BuildConnection::new(server.url("test").unwrap()).with_heartbeat(HeartbeatPolicy {
+                interval: Duration::from_millis(10),
+                timeout: Duration::from_secs(5),
+            });
+        let mut client = AsyncClient::new(con);
+        let _events = client.connect().await.unwrap();
+        assert_eq!(client.latency(), None);
+
+        let start = Instant::now();
+        while client.latency().is_none() && start.elapsed() < Duration::from_secs(5) {
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert!(client.latency().unwrap() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_pong_latency() {
+        let started = Instant::now() - Duration::from_secs(10);
+        let payload = match ping(started) {
+            tungstenite::Message::Ping(x) => x,
+            x => panic!("Expected a ping, got {x:?}"),
+        };
+        assert!(pong_latency(started, &payload).unwrap() < Duration::from_secs(1));
+
+        // A late pong is timed from its own ping, not from the last one sent
+        let sent = (started.elapsed() - Duration::from_secs(3)).as_micros() as u64;
+        let latency = pong_latency(started, &sent.to_be_bytes()).unwrap();
+        assert!(latency >= Duration::from_secs(3));
+
+        assert!(pong_latency(started, &[]).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_timeout() {
+        let server = MockServer::start().await.unwrap();
+        let con = BuildConnection::new(server.url("test").unwrap())
+            .with_reconnect(ReconnectPolicy::disabled())
+            .with_heartbeat(HeartbeatPolicy {
+                interval: Duration::from_millis(10),
+                timeout: Duration::from_millis(100),
+            });
+        let mut client = AsyncClient::new(con);
+        let mut events = client.connect().await.unwrap();
+        assert!(matches!(
+            events.next().await,
+            Some(NetworkEvent::Connected(_))
+        ));
+
+        let start = Instant::now();
+        server.hang();
+        let events: Vec<_> = timeout(Duration::from_secs(5), events.collect())
+            .await
+            .expect("the dead connection was not detected");
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        assert!(events.iter().any(|x| matches!(
+            x,
+            NetworkEvent::Error(None, ClientError::ChannelClosed(msg)) if msg.contains("did not respond")
+        )));
+        assert!(matches!(events.last(), Some(NetworkEvent::Disconnected(_))));
+    }
+
+    #[tokio::test]
+    async fn test_disconnect() {
+        let server = MockServer::start().await.unwrap();
+        let mut client = AsyncClient::new(BuildConnection::new(server.url("test").unwrap()));
+        let mut events = client.connect().await.unwrap();
+        assert!(matches!(
+            events.next().await,
+            Some(NetworkEvent::Connected(_))
+        ));
+
+        client.send_raw_message(tungstenite::Message::Text("bye".to_string()));
+        client.disconnect().await;
+        assert!(!client.is_running());
+        assert_eq!(
+            server.received(),
+            vec![tungstenite::Message::Text("bye".to_string())]
+        );
+
+        // The identity token, then the end of the connection
+        assert!(matches!(
+            events.next().await,
+            Some(NetworkEvent::Message(..))
+        ));
+        assert!(matches!(
+            events.next().await,
+            Some(NetworkEvent::Disconnected(_))
+        ));
+        assert!(events.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rejected_credentials() {
+        let server = MockServer::start().await.unwrap();
+        let dir = std::env::temp_dir().join(format!("spacetimedb-{}", uuid::Uuid::new_v4()));
+        let store = FileCredentials::new(dir.join("game.json"));
+        store
+            .save(&IdentityTokenJson::new("ab01", "unknown-token"))
+            .unwrap();
+
+        let con = BuildConnection::new(server.url("test").unwrap());
+        let mut client = AsyncClient::new(con).with_credentials(store.clone());
+        let _events = client.connect().await.unwrap();
+
+        // A new identity, saved in place of the rejected one
+        assert_eq!(
+            client.identity().unwrap().identity,
+            server.identity().identity
+        );
+        assert_eq!(
+            store.load().unwrap().unwrap().token,
+            server.identity().token
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_calls_in_order() {
+        let url = Uri::from_static("ws://127.0.0.1:3000/database/subscribe?name_or_address=test");
+        let con = BuildConnection::new(url).with_auth(IdentityTokenJson::new("ab01", "token"));
+        let shared = Shared::new(&con);
+
+        let mut calls: Vec<_> = ["move_player", "create_new_player", "move_player"]
+            .iter()
+            .map(|reducer| pending_call(&shared, reducer, Vec::new(), Instant::now()))
+            .collect();
+
+        // Another client
+        assert!(shared
+            .resolve(&con, &event("ffff", "move_player", "failed"))
+            .is_none());
+        assert!(calls[0].try_recv().is_err());
+
+        assert!(matches!(
+            shared.resolve(&con, &event("AB01", "move_player", "failed")),
+            Some(ClientError::ServerRejected { .. })
+        ));
+        assert!(shared
+            .resolve(&con, &event("ab01", "move_player", "committed"))
+            .is_none());
+        assert!(!calls[0].try_recv().unwrap().is_committed());
+        assert!(calls[1].try_recv().is_err());
+        assert!(calls[2].try_recv().unwrap().is_committed());
+        assert_eq!(shared.pending().len(), 1);
+    }
+
+    #[test]
+    fn test_resolve_calls_by_args() {
+        let url = Uri::from_static("ws://127.0.0.1:3000/database/subscribe?name_or_address=test");
+        let con = BuildConnection::new(url).with_auth(IdentityTokenJson::new("ab01", "token"));
+        let shared = Shared::new(&con);
+
+        let mut first = pending_call(&shared, "move_player", vec![1], Instant::now());
+        let mut second = pending_call(&shared, "move_player", vec![2], Instant::now());
+
+        let mut ev = event("ab01", "move_player", "committed");
+        ev.function_call.arg_bytes = vec![2];
+        assert!(shared.resolve(&con, &ev).is_none());
+        assert!(first.try_recv().is_err());
+        assert!(second.try_recv().unwrap().is_committed());
+
+        // No call with these arguments
+        ev.function_call.arg_bytes = vec![3];
+        assert!(shared.resolve(&con, &ev).is_none());
+        assert_eq!(shared.pending().len(), 1);
+    }
+
+    #[test]
+    fn test_expire_calls() {
+        let url = Uri::from_static("ws://127.0.0.1:3000/database/subscribe?name_or_address=test");
+        let shared = Shared::new(&BuildConnection::new(url));
+
+        let long_ago = Instant::now() - CALL_TIMEOUT * 2;
+        let mut expired = pending_call(&shared, "move_player", Vec::new(), long_ago);
+        let dropped = pending_call(&shared, "move_player", Vec::new(), Instant::now());
+        drop(dropped);
+        let mut waiting = pending_call(&shared, "fire", Vec::new(), Instant::now());
+
+        Shared::expire_calls(&mut shared.pending());
+        assert_eq!(shared.pending().len(), 1);
+        assert!(matches!(expired.try_recv(), Err(TryRecvError::Closed)));
+        assert!(matches!(waiting.try_recv(), Err(TryRecvError::Empty)));
+
+        // The oldest calls are forgotten to make room for the new ones
+        let _calls: Vec<_> = (0..MAX_PENDING_CALLS)
+            .map(|_| pending_call(&shared, "move_player", Vec::new(), Instant::now()))
+            .collect();
+        Shared::expire_calls(&mut shared.pending());
+        assert!(shared.pending().len() < MAX_PENDING_CALLS);
+        assert!(matches!(waiting.try_recv(), Err(TryRecvError::Closed)));
+    }
+}
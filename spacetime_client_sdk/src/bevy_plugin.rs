@@ -0,0 +1,332 @@
+//! A Bevy plugin, that owns the connection & turns its messages into Bevy events.
+//!
+//! ```ignore
+//! App::new()
+//!     .add_plugin(SpacetimeDbPlugin::new(Client::new("127.0.0.1:3000", "game")?))
+//!     .add_table::<PlayerComponent>()
+//!     .add_system(on_player_moved);
+//!
+//! fn on_player_moved(mut updates: EventReader<RowUpdated<PlayerComponent>>) {
+//!     for x in updates.iter() { .. }
+//! }
+//! ```
+//! All the events are sent in [CoreStage::PreUpdate], so are ready for the systems of the
+//! frame.
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+use bevy_app::{App, CoreStage, Plugin};
+use bevy_ecs::prelude::*;
+use log::error;
+
+use crate::cache::RowChange;
+use crate::messages::EventJson;
+use crate::table::TableRow;
+use crate::web_socket::{Client, NetworkEvent};
+
+/// The connection to SpacetimeDB, inserted by the [SpacetimeDbPlugin]
+#[derive(Resource)]
+pub struct SpacetimeDb {
+    client: Client,
+}
+
+impl Deref for SpacetimeDb {
+    type Target = Client;
+
+    fn deref(&self) -> &Self::Target {
+        &self.client
+    }
+}
+
+impl DerefMut for SpacetimeDb {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.client
+    }
+}
+
+/// The event of a reducer call, by any client, that changed the subscribed rows
+pub struct ReducerEvent(pub EventJson);
+
+pub struct RowInserted<T>(pub T);
+
+pub struct RowUpdated<T> {
+    pub old: T,
+    pub new: T,
+}
+
+pub struct RowDeleted<T>(pub T);
+
+/// The changes to the table `T` since the last frame, filled by the cache of the client
+#[derive(Resource)]
+struct RowChanges<T>(Arc<Mutex<Vec<RowChange<T>>>>);
+
+/// Connect at startup with the [Client], then send every frame its [NetworkEvent]s and
+/// [ReducerEvent]s. The rows of the tables registered with [SpacetimeDbAppExt::add_table]
+/// are sent as [RowInserted], [RowUpdated] & [RowDeleted].
+pub struct SpacetimeDbPlugin {
+    // `Plugin::build` only borrows the plugin, so the client is taken from here
+    client: Mutex<Option<Client>>,
+}
+
+impl SpacetimeDbPlugin {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client: Mutex::new(Some(client)),
+        }
+    }
+}
+
+impl Plugin for SpacetimeDbPlugin {
+    fn build(&self, app: &mut App) {
+        let client = self
+            .client
+            .lock()
+            .expect("failed to lock the client")
+            .take()
+            .expect("the SpacetimeDbPlugin is added more than once");
+
+        app.insert_resource(SpacetimeDb { client })
+            .add_event::<NetworkEvent>()
+            .add_event::<ReducerEvent>()
+            .add_startup_system(connect)
+            .add_system_to_stage(CoreStage::PreUpdate, poll_events);
+    }
+}
+
+pub trait SpacetimeDbAppExt {
+    /// Send the changes to the rows of the table `T` as events. Must be called after adding
+    /// the [SpacetimeDbPlugin]
+    fn add_table<T: TableRow + Clone + Send + Sync + 'static>(&mut self) -> &mut Self;
+}
+
+impl SpacetimeDbAppExt for App {
+    fn add_table<T: TableRow + Clone + Send + Sync + 'static>(&mut self) -> &mut Self {
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let queue = changes.clone();
+        self.world
+            .get_resource::<SpacetimeDb>()
+            .expect("add the SpacetimeDbPlugin before the tables")
+            .cache()
+            .on_change::<T>(move |change| {
+                queue
+                    .lock()
+                    .expect("failed to lock the row changes")
+                    .push(change.clone())
+            });
+
+        self.insert_resource(RowChanges(changes))
+            .add_event::<RowInserted<T>>()
+            .add_event::<RowUpdated<T>>()
+            .add_event::<RowDeleted<T>>()
+            .add_system_to_stage(CoreStage::PreUpdate, send_rows::<T>.after(poll_events))
+    }
+}
+
+fn connect(mut db: ResMut<SpacetimeDb>, mut network: EventWriter<NetworkEvent>) {
+    if let Err(err) = db.connect() {
+        error!("Fail to connect to SpacetimeDB: {err}");
+        network.send(NetworkEvent::Error(None, err));
+    }
+}
+
+fn poll_events(
+    db: Res<SpacetimeDb>,
+    mut network: EventWriter<NetworkEvent>,
+    mut reducers: EventWriter<ReducerEvent>,
+) {
+    if !db.is_running() {
+        return;
+    }
+    while let Some(ev) = db.try_recv() {
+        if let NetworkEvent::Message(_, msg) = &ev {
+            if let Some(x) = msg.event() {
+                reducers.send(ReducerEvent(x.clone()));
+            }
+        }
+        network.send(ev);
+    }
+}
+
+fn send_rows<T: TableRow + Send + Sync + 'static>(
+    changes: Res<RowChanges<T>>,
+    mut inserted: EventWriter<RowInserted<T>>,
+    mut updated: EventWriter<RowUpdated<T>>,
+    mut deleted: EventWriter<RowDeleted<T>>,
+) {
+    let mut changes = changes.0.lock().expect("failed to lock the row changes");
+    for change in changes.drain(..) {
+        match change {
+            RowChange::Insert(x) => inserted.send(RowInserted(x)),
+            RowChange::Update { old, new } => updated.send(RowUpdated { old, new }),
+            RowChange::Delete(x) => deleted.send(RowDeleted(x)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ClientError;
+    use crate::messages::{
+        EventJson, FunctionCallJson, SpaceDbResponse, SubscriptionUpdateJson, TableOp,
+        TableRowOperationJson, TableUpdateJson, TransactionUpdateJson,
+    };
+    use crate::mock::MockServer;
+    use crate::table::{schema, Column, Columns};
+    use crate::ws::BuildConnection;
+    use spacetimedb::spacetimedb_lib::TupleDef;
+    use spacetimedb::TypeValue;
+    use std::time::{Duration, Instant};
+    use tokio::runtime::Runtime;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Player {
+        entity_id: u64,
+        input: u8,
+    }
+
+    impl TableRow for Player {
+        fn table_name() -> &'static str {
+            "PlayerComponent"
+        }
+
+        fn schema() -> TupleDef {
+            schema(
+                "PlayerComponent",
+                vec![("entity_id", u64::type_def()), ("input", u8::type_def())],
+            )
+        }
+
+        fn primary_key() -> Option<&'static str> {
+            Some("entity_id")
+        }
+
+        fn from_columns(columns: &Columns) -> Result<Self, ClientError> {
+            Ok(Self {
+                entity_id: columns.get("entity_id")?,
+                input: columns.get("input")?,
+            })
+        }
+    }
+
+    /// The row events received by the systems, in order
+    #[derive(Resource, Default)]
+    struct Received(Vec<RowChange<Player>>);
+
+    fn receive(
+        mut received: ResMut<Received>,
+        mut inserted: EventReader<RowInserted<Player>>,
+        mut updated: EventReader<RowUpdated<Player>>,
+        mut deleted: EventReader<RowDeleted<Player>>,
+    ) {
+        let received = &mut received.0;
+        received.extend(inserted.iter().map(|x| RowChange::Insert(x.0.clone())));
+        received.extend(updated.iter().map(|x| RowChange::Update {
+            old: x.old.clone(),
+            new: x.new.clone(),
+        }));
+        received.extend(deleted.iter().map(|x| RowChange::Delete(x.0.clone())));
+    }
+
+    fn op(op: TableOp, entity_id: u64, input: u8) -> TableRowOperationJson {
+        TableRowOperationJson {
+            op,
+            row_pk: format!("{entity_id:02x}{input:02x}"),
+            row: vec![TypeValue::U64(entity_id), TypeValue::U8(input)],
+        }
+    }
+
+    fn players(ops: Vec<TableRowOperationJson>) -> SubscriptionUpdateJson {
+        SubscriptionUpdateJson {
+            table_updates: vec![TableUpdateJson {
+                table_id: 1,
+                table_name: "PlayerComponent".to_string(),
+                table_row_operations: ops,
+            }],
+        }
+    }
+
+    /// Run the frames of `app` until `len` row events are received
+    fn update_until(app: &mut App, len: usize) -> &[RowChange<Player>] {
+        let start = Instant::now();
+        while app.world.resource::<Received>().0.len() < len {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "Timeout waiting for the row events"
+            );
+            app.update();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        &app.world.resource::<Received>().0
+    }
+
+    #[test]
+    fn test_row_events() {
+        let rt = Runtime::new().unwrap();
+        let server = rt.block_on(MockServer::start()).unwrap();
+        server.set_snapshot(players(vec![
+            op(TableOp::Insert, 0, 0),
+            op(TableOp::Insert, 1, 0),
+        ]));
+
+        let con = BuildConnection::new(server.url("test").unwrap());
+        let client = Client::with_connection(con).unwrap();
+        let mut app = App::new();
+        app.add_plugin(SpacetimeDbPlugin::new(client))
+            .add_table::<Player>()
+            .init_resource::<Received>()
+            .add_system(receive);
+
+        let received = update_until(&mut app, 2);
+        assert!(received.contains(&RowChange::Insert(Player {
+            entity_id: 0,
+            input: 0
+        })));
+        assert!(received.contains(&RowChange::Insert(Player {
+            entity_id: 1,
+            input: 0
+        })));
+
+        // The player 1 moves & the player 0 leaves
+        server.send(SpaceDbResponse::TransactionUpdate(TransactionUpdateJson {
+            event: EventJson {
+                timestamp: 0,
+                status: "committed".to_string(),
+                caller_identity: server.identity().identity.clone(),
+                function_call: FunctionCallJson {
+                    reducer: "move_player".to_string(),
+                    arg_bytes: Vec::new(),
+                },
+                message: String::new(),
+                energy_quanta_used: 0,
+                host_execution_duration_micros: 0,
+            },
+            subscription_update: players(vec![
+                op(TableOp::Delete, 1, 0),
+                op(TableOp::Insert, 1, 4),
+                op(TableOp::Delete, 0, 0),
+            ]),
+        }));
+
+        let received = update_until(&mut app, 4);
+        assert_eq!(
+            received[2..],
+            [
+                RowChange::Update {
+                    old: Player {
+                        entity_id: 1,
+                        input: 0
+                    },
+                    new: Player {
+                        entity_id: 1,
+                        input: 4
+                    },
+                },
+                RowChange::Delete(Player {
+                    entity_id: 0,
+                    input: 0
+                }),
+            ]
+        );
+    }
+}
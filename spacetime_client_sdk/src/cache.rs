@@ -0,0 +1,525 @@
+//! A local replica of the subscribed tables.
+//!
+//! The [ClientCache] applies the initial [SubscriptionUpdateJson] and each
+//! [TransactionUpdateJson] to an in-memory map per table, keyed by `row_pk`, so the
+//! current rows can be queried at any time.
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use log::warn;
+use spacetimedb::spacetimedb_lib::TupleDef;
+use spacetimedb::TypeValue;
+
+use crate::errors::ClientError;
+use crate::messages::{
+    SpaceDbResponse, SubscriptionUpdateJson, TableOp, TableSchemas, TableUpdateJson,
+    TransactionUpdateJson,
+};
+use crate::table::{Columns, TableRow};
+
+/// A change to a row of a table
+#[derive(Debug, Clone, PartialEq)]
+pub enum RowChange<T> {
+    Insert(T),
+    /// Only reported for tables with a known primary key, see [TableRow::primary_key]
+    Update {
+        old: T,
+        new: T,
+    },
+    Delete(T),
+}
+
+impl<T> RowChange<T> {
+    fn try_map<U, E>(&self, f: impl Fn(&T) -> Result<U, E>) -> Result<RowChange<U>, E> {
+        Ok(match self {
+            RowChange::Insert(x) => RowChange::Insert(f(x)?),
+            RowChange::Update { old, new } => RowChange::Update {
+                old: f(old)?,
+                new: f(new)?,
+            },
+            RowChange::Delete(x) => RowChange::Delete(f(x)?),
+        })
+    }
+}
+
+/// The undecoded values of a row
+type Row = Vec<TypeValue>;
+type Callback<T> = Arc<Mutex<dyn FnMut(&RowChange<T>) + Send>>;
+/// The changes to a table, with the callbacks to report them
+type TableChanges = (Vec<Callback<Row>>, Vec<RowChange<Row>>);
+
+/// The changes applied to the cache, not yet reported to the callbacks.
+///
+/// Call [Self::notify] once the cache is unlocked, so the callbacks are free to query it.
+#[must_use = "the callbacks only run with `notify`"]
+#[derive(Default)]
+pub struct AppliedChanges {
+    tables: Vec<TableChanges>,
+}
+
+impl AppliedChanges {
+    fn extend(&mut self, other: AppliedChanges) {
+        self.tables.extend(other.tables);
+    }
+
+    /// Run the callbacks of each change
+    pub fn notify(self) {
+        for (callbacks, changes) in self.tables {
+            for f in &callbacks {
+                // A callback that panicked before is still called
+                let mut f = f.lock().unwrap_or_else(PoisonError::into_inner);
+                for change in &changes {
+                    f(change);
+                }
+            }
+        }
+    }
+}
+
+/// The rows of a table, keyed by `row_pk`
+#[derive(Default)]
+pub struct TableCache {
+    schema: Option<TupleDef>,
+    primary_key: Option<String>,
+    rows: HashMap<String, Row>,
+    callbacks: Vec<Callback<Row>>,
+}
+
+impl TableCache {
+    fn new(schema: Option<TupleDef>) -> Self {
+        Self {
+            schema,
+            ..Default::default()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub fn get(&self, row_pk: &str) -> Option<&[TypeValue]> {
+        self.rows.get(row_pk).map(|x| x.as_slice())
+    }
+
+    /// Iterate the `(row_pk, row)` pairs
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[TypeValue])> {
+        self.rows
+            .iter()
+            .map(|(pk, row)| (pk.as_str(), row.as_slice()))
+    }
+
+    fn primary_key_pos(&self) -> Option<usize> {
+        let key = self.primary_key.as_deref()?;
+        self.schema
+            .as_ref()?
+            .elements
+            .iter()
+            .position(|x| x.name.as_deref() == Some(key))
+    }
+
+    /// Apply the row operations. When `snapshot` is set the update has the full contents of
+    /// the table, so the rows not in it are deleted.
+    fn apply(&mut self, update: &TableUpdateJson, snapshot: bool) -> AppliedChanges {
+        let mut deleted = Vec::new();
+        let mut inserted = Vec::new();
+
+        if snapshot {
+            let keep: HashSet<_> = update
+                .table_row_operations
+                .iter()
+                .filter(|x| x.op != TableOp::Delete)
+                .map(|x| x.row_pk.as_str())
+                .collect();
+            let gone: Vec<_> = self
+                .rows
+                .keys()
+                .filter(|x| !keep.contains(x.as_str()))
+                .cloned()
+                .collect();
+            for row_pk in gone {
+                deleted.extend(self.rows.remove(&row_pk));
+            }
+        }
+
+        for op in &update.table_row_operations {
+            match op.op {
+                TableOp::Delete => deleted.extend(self.rows.remove(&op.row_pk)),
+                TableOp::Insert | TableOp::Update => {
+                    if self
+                        .rows
+                        .insert(op.row_pk.clone(), op.row.clone())
+                        .is_none()
+                    {
+                        inserted.push(op.row.clone());
+                    }
+                }
+            }
+        }
+
+        // A row with the same primary key deleted & inserted in the same transaction was updated
+        let mut changes = Vec::with_capacity(deleted.len() + inserted.len());
+        let key = self.primary_key_pos();
+        for new in inserted {
+            let old = key.and_then(|key| {
+                deleted
+                    .iter()
+                    .position(|old| old.get(key).is_some() && old.get(key) == new.get(key))
+            });
+            match old {
+                Some(old) => changes.push(RowChange::Update {
+                    old: deleted.swap_remove(old),
+                    new,
+                }),
+                None => changes.push(RowChange::Insert(new)),
+            }
+        }
+        changes.extend(deleted.into_iter().map(RowChange::Delete));
+
+        if changes.is_empty() || self.callbacks.is_empty() {
+            return AppliedChanges::default();
+        }
+        AppliedChanges {
+            tables: vec![(self.callbacks.clone(), changes)],
+        }
+    }
+}
+
+impl TableUpdateJson {
+    fn empty(table_name: &str) -> Self {
+        Self {
+            table_id: 0,
+            table_name: table_name.to_string(),
+            table_row_operations: Vec::new(),
+        }
+    }
+}
+
+fn decode<T: TableRow>(schema: Option<&TupleDef>, row: &[TypeValue]) -> Result<T, ClientError> {
+    match schema {
+        Some(schema) => T::from_columns(&Columns::new(schema, row)),
+        None => T::from_columns(&Columns::new(&T::schema(), row)),
+    }
+}
+
+/// The local replica of all the subscribed tables
+pub struct ClientCache {
+    schemas: Arc<TableSchemas>,
+    tables: HashMap<String, TableCache>,
+}
+
+impl ClientCache {
+    /// The `schemas` are used to decode the rows of the tables, see
+    /// [crate::ws::BuildConnection::with_table]
+    pub fn new(schemas: Arc<TableSchemas>) -> Self {
+        Self {
+            schemas,
+            tables: HashMap::new(),
+        }
+    }
+
+    pub fn table(&self, table_name: &str) -> Option<&TableCache> {
+        self.tables.get(table_name)
+    }
+
+    fn table_mut(&mut self, table_name: &str) -> &mut TableCache {
+        self.tables
+            .entry(table_name.to_string())
+            .or_insert_with(|| TableCache::new(self.schemas.get(table_name).cloned()))
+    }
+
+    fn table_of<T: TableRow>(&mut self) -> &mut TableCache {
+        let table = self.table_mut(T::table_name());
+        table.schema.get_or_insert_with(T::schema);
+        if table.primary_key.is_none() {
+            table.primary_key = T::primary_key().map(|x| x.to_string());
+        }
+        table
+    }
+
+    /// The current rows of the table `T`
+    pub fn rows<T: TableRow>(&self) -> Result<Vec<T>, ClientError> {
+        match self.table(T::table_name()) {
+            Some(table) => table
+                .rows
+                .values()
+                .map(|row| decode(table.schema.as_ref(), row))
+                .collect(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Lookup a row of the table `T` by its `row_pk`
+    pub fn find<T: TableRow>(&self, row_pk: &str) -> Option<Result<T, ClientError>> {
+        let table = self.table(T::table_name())?;
+        let row = table.get(row_pk)?;
+        Some(decode(table.schema.as_ref(), row))
+    }
+
+    /// Call `f` for every change to the rows of the table `T`
+    pub fn on_change<T: TableRow + 'static>(
+        &mut self,
+        mut f: impl FnMut(&RowChange<T>) + Send + 'static,
+    ) {
+        let table = self.table_of::<T>();
+        let schema = table.schema.clone();
+
+        let callback = move |change: &RowChange<Row>| match change
+            .try_map(|row| decode::<T>(schema.as_ref(), row))
+        {
+            Ok(change) => f(&change),
+            Err(err) => warn!("failed to decode a row of {}: {err}", T::table_name()),
+        };
+        table.callbacks.push(Arc::new(Mutex::new(callback)));
+    }
+
+    pub fn on_insert<T: TableRow + 'static>(&mut self, mut f: impl FnMut(&T) + Send + 'static) {
+        self.on_change::<T>(move |change| {
+            if let RowChange::Insert(row) = change {
+                f(row)
+            }
+        });
+    }
+
+    pub fn on_update<T: TableRow + 'static>(&mut self, mut f: impl FnMut(&T, &T) + Send + 'static) {
+        self.on_change::<T>(move |change| {
+            if let RowChange::Update { old, new } = change {
+                f(old, new)
+            }
+        });
+    }
+
+    pub fn on_delete<T: TableRow + 'static>(&mut self, mut f: impl FnMut(&T) + Send + 'static) {
+        self.on_change::<T>(move |change| {
+            if let RowChange::Delete(row) = change {
+                f(row)
+            }
+        });
+    }
+
+    /// Apply a [SubscriptionUpdateJson], that has the full contents of the subscribed tables.
+    ///
+    /// The tables not in the update are no longer subscribed, or are empty, so are cleared.
+    pub fn apply_subscription_update(&mut self, update: &SubscriptionUpdateJson) -> AppliedChanges {
+        let mut changes = AppliedChanges::default();
+        for (table_name, table) in &mut self.tables {
+            if !update
+                .table_updates
+                .iter()
+                .any(|x| &x.table_name == table_name)
+            {
+                changes.extend(table.apply(&TableUpdateJson::empty(table_name), true));
+            }
+        }
+        for table in &update.table_updates {
+            changes.extend(self.table_mut(&table.table_name).apply(table, true));
+        }
+        changes
+    }
+
+    pub fn apply_transaction_update(&mut self, update: &TransactionUpdateJson) -> AppliedChanges {
+        let mut changes = AppliedChanges::default();
+        for table in &update.subscription_update.table_updates {
+            changes.extend(self.table_mut(&table.table_name).apply(table, false));
+        }
+        changes
+    }
+
+    /// Apply the message if it changes the tables, otherwise is ignored.
+    ///
+    /// The callbacks run with [AppliedChanges::notify], after unlocking the cache.
+    pub fn apply(&mut self, msg: &SpaceDbResponse) -> AppliedChanges {
+        match msg {
+            SpaceDbResponse::SubscriptionUpdate(update) => self.apply_subscription_update(update),
+            SpaceDbResponse::TransactionUpdate(update) => self.apply_transaction_update(update),
+            _ => AppliedChanges::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{EventJson, FunctionCallJson, TableRowOperationJson};
+    use crate::table::Column;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Player {
+        entity_id: u64,
+        input: u8,
+    }
+
+    impl TableRow for Player {
+        fn table_name() -> &'static str {
+            "PlayerComponent"
+        }
+
+        fn schema() -> TupleDef {
+            crate::table::schema(
+                "PlayerComponent",
+                vec![("entity_id", u64::type_def()), ("input", u8::type_def())],
+            )
+        }
+
+        fn primary_key() -> Option<&'static str> {
+            Some("entity_id")
+        }
+
+        fn from_columns(columns: &Columns) -> Result<Self, ClientError> {
+            Ok(Self {
+                entity_id: columns.get("entity_id")?,
+                input: columns.get("input")?,
+            })
+        }
+    }
+
+    fn op(op: TableOp, row_pk: &str, entity_id: u64, input: u8) -> TableRowOperationJson {
+        TableRowOperationJson {
+            op,
+            row_pk: row_pk.to_string(),
+            row: vec![TypeValue::U64(entity_id), TypeValue::U8(input)],
+        }
+    }
+
+    fn update(ops: Vec<TableRowOperationJson>) -> SubscriptionUpdateJson {
+        SubscriptionUpdateJson {
+            table_updates: vec![TableUpdateJson {
+                table_id: 1,
+                table_name: "PlayerComponent".to_string(),
+                table_row_operations: ops,
+            }],
+        }
+    }
+
+    fn transaction(ops: Vec<TableRowOperationJson>) -> SpaceDbResponse {
+        SpaceDbResponse::TransactionUpdate(TransactionUpdateJson {
+            event: EventJson {
+                timestamp: 0,
+                status: "committed".to_string(),
+                caller_identity: "ab01".to_string(),
+                function_call: FunctionCallJson {
+                    reducer: "move_player".to_string(),
+                    arg_bytes: Vec::new(),
+                },
+                message: String::new(),
+                energy_quanta_used: 0,
+                host_execution_duration_micros: 0,
+            },
+            subscription_update: update(ops),
+        })
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut cache = ClientCache::new(Default::default());
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let log = changes.clone();
+        cache.on_change::<Player>(move |x| log.lock().unwrap().push(x.clone()));
+
+        cache
+            .apply_subscription_update(&update(vec![
+                op(TableOp::Insert, "a", 0, 0),
+                op(TableOp::Insert, "b", 1, 0),
+            ]))
+            .notify();
+        assert_eq!(cache.table("PlayerComponent").unwrap().len(), 2);
+
+        // Moving the player 1 replace its row
+        cache
+            .apply(&transaction(vec![
+                op(TableOp::Delete, "b", 1, 0),
+                op(TableOp::Insert, "c", 1, 4),
+            ]))
+            .notify();
+        assert_eq!(
+            cache.find::<Player>("c").unwrap().unwrap(),
+            Player {
+                entity_id: 1,
+                input: 4
+            }
+        );
+        assert!(cache.find::<Player>("b").is_none());
+
+        // The player 0 is gone from a new snapshot
+        cache
+            .apply_subscription_update(&update(vec![op(TableOp::Insert, "c", 1, 4)]))
+            .notify();
+        assert_eq!(cache.rows::<Player>().unwrap().len(), 1);
+
+        // The table is no longer subscribed
+        cache
+            .apply_subscription_update(&SubscriptionUpdateJson {
+                table_updates: Vec::new(),
+            })
+            .notify();
+        assert!(cache.table("PlayerComponent").unwrap().is_empty());
+
+        let changes = changes.lock().unwrap();
+        assert_eq!(changes.len(), 5);
+        assert_eq!(
+            changes[2],
+            RowChange::Update {
+                old: Player {
+                    entity_id: 1,
+                    input: 0
+                },
+                new: Player {
+                    entity_id: 1,
+                    input: 4
+                }
+            }
+        );
+        assert_eq!(
+            changes[3],
+            RowChange::Delete(Player {
+                entity_id: 0,
+                input: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_callbacks_query_the_cache() {
+        let cache = Arc::new(Mutex::new(ClientCache::new(Default::default())));
+        let seen = Arc::new(Mutex::new(Vec::new()));
+
+        let (shared, log) = (cache.clone(), seen.clone());
+        cache.lock().unwrap().on_insert::<Player>(move |_| {
+            let len = shared.lock().unwrap().rows::<Player>().unwrap().len();
+            log.lock().unwrap().push(len);
+        });
+
+        // Like the connection does, the guard is dropped before the callbacks run
+        let changes = cache.lock().unwrap().apply(&transaction(vec![
+            op(TableOp::Insert, "a", 0, 0),
+            op(TableOp::Insert, "b", 1, 0),
+        ]));
+        changes.notify();
+        assert_eq!(*seen.lock().unwrap(), vec![2, 2]);
+    }
+
+    #[test]
+    fn test_callback_panic() {
+        let mut cache = ClientCache::new(Default::default());
+        let calls = Arc::new(Mutex::new(0));
+        let log = calls.clone();
+        cache.on_insert::<Player>(move |x| {
+            *log.lock().unwrap() += 1;
+            assert_ne!(x.entity_id, 0, "bad player");
+        });
+
+        let changes = cache.apply(&transaction(vec![op(TableOp::Insert, "a", 0, 0)]));
+        let notify = std::panic::AssertUnwindSafe(move || changes.notify());
+        assert!(std::panic::catch_unwind(notify).is_err());
+
+        // The callback is still called for the next changes
+        cache
+            .apply(&transaction(vec![op(TableOp::Insert, "b", 1, 0)]))
+            .notify();
+        assert_eq!(cache.rows::<Player>().unwrap().len(), 2);
+        assert_eq!(*calls.lock().unwrap(), 2);
+    }
+}
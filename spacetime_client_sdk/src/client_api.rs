@@ -45,6 +45,7 @@ pub enum Message_oneof_type {
     event(Event),
     transactionUpdate(TransactionUpdate),
     identityToken(IdentityToken),
+    subscribe(Subscribe),
 }
 
 impl Message {
@@ -296,6 +297,55 @@ impl Message {
             IdentityToken::new()
         }
     }
+
+    // .client_api.Subscribe subscribe = 6;
+
+
+    pub fn get_subscribe(&self) -> &Subscribe {
+        match self.field_type {
+            ::std::option::Option::Some(Message_oneof_type::subscribe(ref v)) => v,
+            _ => <Subscribe as ::protobuf::Message>::default_instance(),
+        }
+    }
+    pub fn clear_subscribe(&mut self) {
+        self.field_type = ::std::option::Option::None;
+    }
+
+    pub fn has_subscribe(&self) -> bool {
+        match self.field_type {
+            ::std::option::Option::Some(Message_oneof_type::subscribe(..)) => true,
+            _ => false,
+        }
+    }
+
+    // Param is passed by value, moved
+    pub fn set_subscribe(&mut self, v: Subscribe) {
+        self.field_type = ::std::option::Option::Some(Message_oneof_type::subscribe(v))
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_subscribe(&mut self) -> &mut Subscribe {
+        if let ::std::option::Option::Some(Message_oneof_type::subscribe(_)) = self.field_type {
+        } else {
+            self.field_type = ::std::option::Option::Some(Message_oneof_type::subscribe(Subscribe::new()));
+        }
+        match self.field_type {
+            ::std::option::Option::Some(Message_oneof_type::subscribe(ref mut v)) => v,
+            _ => panic!(),
+        }
+    }
+
+    // Take field
+    pub fn take_subscribe(&mut self) -> Subscribe {
+        if self.has_subscribe() {
+            match self.field_type.take() {
+                ::std::option::Option::Some(Message_oneof_type::subscribe(v)) => v,
+                _ => panic!(),
+            }
+        } else {
+            Subscribe::new()
+        }
+    }
 }
 
 impl ::protobuf::Message for Message {
@@ -325,6 +375,11 @@ impl ::protobuf::Message for Message {
                 return false;
             }
         }
+        if let Some(Message_oneof_type::subscribe(ref v)) = self.field_type {
+            if !v.is_initialized() {
+                return false;
+            }
+        }
         true
     }
 
@@ -362,6 +417,12 @@ impl ::protobuf::Message for Message {
                     }
                     self.field_type = ::std::option::Option::Some(Message_oneof_type::identityToken(is.read_message()?));
                 },
+                6 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeLengthDelimited {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    self.field_type = ::std::option::Option::Some(Message_oneof_type::subscribe(is.read_message()?));
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -396,6 +457,10 @@ impl ::protobuf::Message for Message {
                     let len = v.compute_size();
                     my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
                 },
+                &Message_oneof_type::subscribe(ref v) => {
+                    let len = v.compute_size();
+                    my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+                },
             };
         }
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
@@ -431,6 +496,11 @@ impl ::protobuf::Message for Message {
                     os.write_raw_varint32(v.get_cached_size())?;
                     v.write_to_with_cached_sizes(os)?;
                 },
+                &Message_oneof_type::subscribe(ref v) => {
+                    os.write_tag(6, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+                    os.write_raw_varint32(v.get_cached_size())?;
+                    v.write_to_with_cached_sizes(os)?;
+                },
             };
         }
         os.write_unknown_fields(self.get_unknown_fields())?;
@@ -496,6 +566,11 @@ impl ::protobuf::Message for Message {
                 Message::has_identityToken,
                 Message::get_identityToken,
             ));
+            fields.push(::protobuf::reflect::accessor::make_singular_message_accessor::<_, Subscribe>(
+                "subscribe",
+                Message::has_subscribe,
+                Message::get_subscribe,
+            ));
             ::protobuf::reflect::MessageDescriptor::new_pb_name::<Message>(
                 "Message",
                 fields,
@@ -517,6 +592,7 @@ impl ::protobuf::Clear for Message {
         self.field_type = ::std::option::Option::None;
         self.field_type = ::std::option::Option::None;
         self.field_type = ::std::option::Option::None;
+        self.field_type = ::std::option::Option::None;
         self.unknown_fields.clear();
     }
 }
@@ -734,6 +810,164 @@ impl ::protobuf::reflect::ProtobufValue for IdentityToken {
     }
 }
 
+#[derive(PartialEq,Clone,Default)]
+pub struct Subscribe {
+    // message fields
+    pub query_strings: ::protobuf::RepeatedField<::std::string::String>,
+    // special fields
+    pub unknown_fields: ::protobuf::UnknownFields,
+    pub cached_size: ::protobuf::CachedSize,
+}
+
+impl<'a> ::std::default::Default for &'a Subscribe {
+    fn default() -> &'a Subscribe {
+        <Subscribe as ::protobuf::Message>::default_instance()
+    }
+}
+
+impl Subscribe {
+    pub fn new() -> Subscribe {
+        ::std::default::Default::default()
+    }
+
+    // repeated string query_strings = 1;
+
+
+    pub fn get_query_strings(&self) -> &[::std::string::String] {
+        &self.query_strings
+    }
+    pub fn clear_query_strings(&mut self) {
+        self.query_strings.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_query_strings(&mut self, v: ::protobuf::RepeatedField<::std::string::String>) {
+        self.query_strings = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_query_strings(&mut self) -> &mut ::protobuf::RepeatedField<::std::string::String> {
+        &mut self.query_strings
+    }
+
+    // Take field
+    pub fn take_query_strings(&mut self) -> ::protobuf::RepeatedField<::std::string::String> {
+        ::std::mem::replace(&mut self.query_strings, ::protobuf::RepeatedField::new())
+    }
+}
+
+impl ::protobuf::Message for Subscribe {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+
+    fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        while !is.eof()? {
+            let (field_number, wire_type) = is.read_tag_unpack()?;
+            match field_number {
+                1 => {
+                    ::protobuf::rt::read_repeated_string_into(wire_type, is, &mut self.query_strings)?;
+                },
+                _ => {
+                    ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
+                },
+            };
+        }
+        ::std::result::Result::Ok(())
+    }
+
+    // Compute sizes of nested messages
+    #[allow(unused_variables)]
+    fn compute_size(&self) -> u32 {
+        let mut my_size = 0;
+        for value in &self.query_strings {
+            my_size += ::protobuf::rt::string_size(1, &value);
+        };
+        my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
+        self.cached_size.set(my_size);
+        my_size
+    }
+
+    fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        for v in &self.query_strings {
+            os.write_string(1, &v)?;
+        };
+        os.write_unknown_fields(self.get_unknown_fields())?;
+        ::std::result::Result::Ok(())
+    }
+
+    fn get_cached_size(&self) -> u32 {
+        self.cached_size.get()
+    }
+
+    fn get_unknown_fields(&self) -> &::protobuf::UnknownFields {
+        &self.unknown_fields
+    }
+
+    fn mut_unknown_fields(&mut self) -> &mut ::protobuf::UnknownFields {
+        &mut self.unknown_fields
+    }
+
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self as &dyn (::std::any::Any)
+    }
+    fn as_any_mut(&mut self) -> &mut dyn (::std::any::Any) {
+        self as &mut dyn (::std::any::Any)
+    }
+    fn into_any(self: ::std::boxed::Box<Self>) -> ::std::boxed::Box<dyn (::std::any::Any)> {
+        self
+    }
+
+    fn descriptor(&self) -> &'static ::protobuf::reflect::MessageDescriptor {
+        Self::descriptor_static()
+    }
+
+    fn new() -> Subscribe {
+        Subscribe::new()
+    }
+
+    fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
+        static descriptor: ::protobuf::rt::LazyV2<::protobuf::reflect::MessageDescriptor> = ::protobuf::rt::LazyV2::INIT;
+        descriptor.get(|| {
+            let mut fields = ::std::vec::Vec::new();
+            fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeString>(
+                "query_strings",
+                |m: &Subscribe| { &m.query_strings },
+                |m: &mut Subscribe| { &mut m.query_strings },
+            ));
+            ::protobuf::reflect::MessageDescriptor::new_pb_name::<Subscribe>(
+                "Subscribe",
+                fields,
+                file_descriptor_proto()
+            )
+        })
+    }
+
+    fn default_instance() -> &'static Subscribe {
+        static instance: ::protobuf::rt::LazyV2<Subscribe> = ::protobuf::rt::LazyV2::INIT;
+        instance.get(Subscribe::new)
+    }
+}
+
+impl ::protobuf::Clear for Subscribe {
+    fn clear(&mut self) {
+        self.query_strings.clear();
+        self.unknown_fields.clear();
+    }
+}
+
+impl ::std::fmt::Debug for Subscribe {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+        ::protobuf::text_format::fmt(self, f)
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for Subscribe {
+    fn as_ref(&self) -> ::protobuf::reflect::ReflectValueRef {
+        ::protobuf::reflect::ReflectValueRef::Message(self)
+    }
+}
+
 #[derive(PartialEq,Clone,Default)]
 pub struct FunctionCall {
     // message fields
@@ -2305,40 +2539,42 @@ impl ::protobuf::reflect::ProtobufValue for TransactionUpdate {
 }
 
 static file_descriptor_proto_data: &'static [u8] = b"\
-    \n\x10client_api.proto\x12\nclient_api\"\xec\x02\n\x07Message\x12@\n\x0c\
+    \n\x10client_api.proto\x12\nclient_api\"\xa5\x03\n\x07Message\x12@\n\x0c\
     functionCall\x18\x01\x20\x01(\x0b2\x18.client_api.FunctionCallH\0R\x0cfu\
     nctionCallB\0\x12R\n\x12subscriptionUpdate\x18\x02\x20\x01(\x0b2\x1e.cli\
     ent_api.SubscriptionUpdateH\0R\x12subscriptionUpdateB\0\x12+\n\x05event\
     \x18\x03\x20\x01(\x0b2\x11.client_api.EventH\0R\x05eventB\0\x12O\n\x11tr\
     ansactionUpdate\x18\x04\x20\x01(\x0b2\x1d.client_api.TransactionUpdateH\
     \0R\x11transactionUpdateB\0\x12C\n\ridentityToken\x18\x05\x20\x01(\x0b2\
-    \x19.client_api.IdentityTokenH\0R\ridentityTokenB\0B\x06\n\x04type:\0\"G\
-    \n\rIdentityToken\x12\x1c\n\x08identity\x18\x01\x20\x01(\x0cR\x08identit\
-    yB\0\x12\x16\n\x05token\x18\x02\x20\x01(\tR\x05tokenB\0:\0\"J\n\x0cFunct\
-    ionCall\x12\x1a\n\x07reducer\x18\x01\x20\x01(\tR\x07reducerB\0\x12\x1c\n\
-    \x08argBytes\x18\x02\x20\x01(\x0cR\x08argBytesB\0:\0\"\x94\x03\n\x05Even\
-    t\x12\x1e\n\ttimestamp\x18\x01\x20\x01(\x04R\ttimestampB\0\x12(\n\x0ecal\
-    lerIdentity\x18\x02\x20\x01(\x0cR\x0ecallerIdentityB\0\x12>\n\x0cfunctio\
-    nCall\x18\x03\x20\x01(\x0b2\x18.client_api.FunctionCallR\x0cfunctionCall\
-    B\0\x122\n\x06status\x18\x04\x20\x01(\x0e2\x18.client_api.Event.StatusR\
-    \x06statusB\0\x12\x1a\n\x07message\x18\x05\x20\x01(\tR\x07messageB\0\x12\
-    .\n\x12energy_quanta_used\x18\x06\x20\x01(\x03R\x10energyQuantaUsedB\0\
-    \x12E\n\x1ehost_execution_duration_micros\x18\x07\x20\x01(\x04R\x1bhostE\
-    xecutionDurationMicrosB\0\"8\n\x06Status\x12\r\n\tcommitted\x10\0\x12\n\
-    \n\x06failed\x10\x01\x12\x11\n\rout_of_energy\x10\x02\x1a\0:\0\"U\n\x12S\
-    ubscriptionUpdate\x12=\n\x0ctableUpdates\x18\x01\x20\x03(\x0b2\x17.clien\
-    t_api.TableUpdateR\x0ctableUpdatesB\0:\0\"\x9c\x01\n\x0bTableUpdate\x12\
-    \x1a\n\x07tableId\x18\x01\x20\x01(\rR\x07tableIdB\0\x12\x1e\n\ttableName\
-    \x18\x02\x20\x01(\tR\ttableNameB\0\x12O\n\x12tableRowOperations\x18\x03\
-    \x20\x03(\x0b2\x1d.client_api.TableRowOperationR\x12tableRowOperationsB\
-    \0:\0\"\xac\x01\n\x11TableRowOperation\x12=\n\x02op\x18\x01\x20\x01(\x0e\
-    2+.client_api.TableRowOperation.OperationTypeR\x02opB\0\x12\x17\n\x06row\
-    _pk\x18\x02\x20\x01(\x0cR\x05rowPkB\0\x12\x12\n\x03row\x18\x03\x20\x01(\
-    \x0cR\x03rowB\0\")\n\rOperationType\x12\n\n\x06DELETE\x10\0\x12\n\n\x06I\
-    NSERT\x10\x01\x1a\0:\0\"\x92\x01\n\x11TransactionUpdate\x12)\n\x05event\
-    \x18\x01\x20\x01(\x0b2\x11.client_api.EventR\x05eventB\0\x12P\n\x12subsc\
-    riptionUpdate\x18\x02\x20\x01(\x0b2\x1e.client_api.SubscriptionUpdateR\
-    \x12subscriptionUpdateB\0:\0B\0b\x06proto3\
+    \x19.client_api.IdentityTokenH\0R\ridentityTokenB\0\x127\n\tsubscribe\
+    \x18\x06\x20\x01(\x0b2\x15.client_api.SubscribeH\0R\tsubscribeB\0B\x06\n\
+    \x04type:\0\"G\n\rIdentityToken\x12\x1c\n\x08identity\x18\x01\x20\x01(\
+    \x0cR\x08identityB\0\x12\x16\n\x05token\x18\x02\x20\x01(\tR\x05tokenB\0:\
+    \0\"4\n\tSubscribe\x12%\n\rquery_strings\x18\x01\x20\x03(\tR\x0cqueryStr\
+    ingsB\0:\0\"J\n\x0cFunctionCall\x12\x1a\n\x07reducer\x18\x01\x20\x01(\tR\
+    \x07reducerB\0\x12\x1c\n\x08argBytes\x18\x02\x20\x01(\x0cR\x08argBytesB\
+    \0:\0\"\x94\x03\n\x05Event\x12\x1e\n\ttimestamp\x18\x01\x20\x01(\x04R\tt\
+    imestampB\0\x12(\n\x0ecallerIdentity\x18\x02\x20\x01(\x0cR\x0ecallerIden\
+    tityB\0\x12>\n\x0cfunctionCall\x18\x03\x20\x01(\x0b2\x18.client_api.Func\
+    tionCallR\x0cfunctionCallB\0\x122\n\x06status\x18\x04\x20\x01(\x0e2\x18.\
+    client_api.Event.StatusR\x06statusB\0\x12\x1a\n\x07message\x18\x05\x20\
+    \x01(\tR\x07messageB\0\x12.\n\x12energy_quanta_used\x18\x06\x20\x01(\x03\
+    R\x10energyQuantaUsedB\0\x12E\n\x1ehost_execution_duration_micros\x18\
+    \x07\x20\x01(\x04R\x1bhostExecutionDurationMicrosB\0\"8\n\x06Status\x12\
+    \r\n\tcommitted\x10\0\x12\n\n\x06failed\x10\x01\x12\x11\n\rout_of_energy\
+    \x10\x02\x1a\0:\0\"U\n\x12SubscriptionUpdate\x12=\n\x0ctableUpdates\x18\
+    \x01\x20\x03(\x0b2\x17.client_api.TableUpdateR\x0ctableUpdatesB\0:\0\"\
+    \x9c\x01\n\x0bTableUpdate\x12\x1a\n\x07tableId\x18\x01\x20\x01(\rR\x07ta\
+    bleIdB\0\x12\x1e\n\ttableName\x18\x02\x20\x01(\tR\ttableNameB\0\x12O\n\
+    \x12tableRowOperations\x18\x03\x20\x03(\x0b2\x1d.client_api.TableRowOper\
+    ationR\x12tableRowOperationsB\0:\0\"\xac\x01\n\x11TableRowOperation\x12=\
+    \n\x02op\x18\x01\x20\x01(\x0e2+.client_api.TableRowOperation.OperationTy\
+    peR\x02opB\0\x12\x17\n\x06row_pk\x18\x02\x20\x01(\x0cR\x05rowPkB\0\x12\
+    \x12\n\x03row\x18\x03\x20\x01(\x0cR\x03rowB\0\")\n\rOperationType\x12\n\
+    \n\x06DELETE\x10\0\x12\n\n\x06INSERT\x10\x01\x1a\0:\0\"\x92\x01\n\x11Tra\
+    nsactionUpdate\x12)\n\x05event\x18\x01\x20\x01(\x0b2\x11.client_api.Even\
+    tR\x05eventB\0\x12P\n\x12subscriptionUpdate\x18\x02\x20\x01(\x0b2\x1e.cl\
+    ient_api.SubscriptionUpdateR\x12subscriptionUpdateB\0:\0B\0b\x06proto3\
 ";
 
 static file_descriptor_proto_lazy: ::protobuf::rt::LazyV2<::protobuf::descriptor::FileDescriptorProto> = ::protobuf::rt::LazyV2::INIT;
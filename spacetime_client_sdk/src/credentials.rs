@@ -0,0 +1,122 @@
+//! Persist the identity of the client between runs.
+//!
+//! The server returns a new identity for each connection without credentials, so a client
+//! that wants to be recognized as the same player must reuse its [IdentityTokenJson].
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+
+use crate::errors::ClientError;
+use crate::messages::IdentityTokenJson;
+
+/// Where the credentials of the client are saved
+pub trait Credentials {
+    /// The saved credentials, if any
+    fn load(&self) -> Result<Option<IdentityTokenJson>, ClientError>;
+    fn save(&self, auth: &IdentityTokenJson) -> Result<(), ClientError>;
+    /// Forget the saved credentials, eg: when the server rejects them
+    fn clear(&self) -> Result<(), ClientError>;
+}
+
+/// Create or truncate the file at `path`, only readable by the user because it holds the token
+#[cfg(unix)]
+fn create(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn create(path: &Path) -> io::Result<File> {
+    File::create(path)
+}
+
+/// Save the credentials as a json file
+#[derive(Debug, Clone)]
+pub struct FileCredentials {
+    path: PathBuf,
+}
+
+impl FileCredentials {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Save the credentials in `{config dir}/spacetimedb/{name}.json`, where the config dir is
+    /// the one of the user, eg: `~/.config` on Linux
+    pub fn in_config_dir(name: &str) -> Result<Self, ClientError> {
+        let dir = dirs::config_dir().ok_or_else(|| anyhow!("Can't find the config directory"))?;
+        Ok(Self::new(
+            dir.join("spacetimedb").join(format!("{name}.json")),
+        ))
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+}
+
+impl Credentials for FileCredentials {
+    fn load(&self) -> Result<Option<IdentityTokenJson>, ClientError> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let json = fs::read_to_string(&self.path)?;
+        Ok(Some(serde_json::from_str(&json)?))
+    }
+
+    fn save(&self, auth: &IdentityTokenJson) -> Result<(), ClientError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        create(&self.path)?.write_all(serde_json::to_string_pretty(auth)?.as_bytes())?;
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<(), ClientError> {
+        match fs::remove_file(&self.path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_credentials() {
+        let dir = std::env::temp_dir().join(format!("spacetimedb-{}", uuid::Uuid::new_v4()));
+        let store = FileCredentials::new(dir.join("nested").join("game.json"));
+        assert!(store.load().unwrap().is_none());
+
+        store
+            .save(&IdentityTokenJson::new("abcd", "token"))
+            .unwrap();
+        let auth = store.load().unwrap().unwrap();
+        assert_eq!(auth.identity, "abcd");
+        assert_eq!(auth.token, "token");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(store.path()).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        store.clear().unwrap();
+        assert!(store.load().unwrap().is_none());
+        // Nothing to clear
+        store.clear().unwrap();
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
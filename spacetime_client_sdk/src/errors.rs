@@ -11,8 +11,21 @@ pub enum ClientError {
     Io(#[from] std::io::Error),
     #[error("Hyper Error: `{0}`")]
     Hyper(#[from] hyper::http::Error),
+    #[error("HTTP Error: `{0}`")]
+    Http(#[from] hyper::Error),
     #[error("Tungstenite Error: `{0}`")]
     Tungstenite(#[from] tungstenite::Error),
+    /// A malformed or unexpected message
+    #[error("Protocol Error: `{0}`")]
+    Protocol(String),
+    /// A message that don't match the schema of the data
+    #[error("Decode Error: `{0}`")]
+    Decode(String),
+    #[error("Channel Closed: `{0}`")]
+    ChannelClosed(String),
+    /// A reducer call of this client that failed in the server
+    #[error("Server Rejected `{reducer}`: {message}")]
+    ServerRejected { reducer: String, message: String },
     #[error("InvalidUri Error: `{0}`")]
     InvalidUri(#[from] http::uri::InvalidUri),
     #[error(transparent)]
@@ -1,7 +1,17 @@
+pub mod async_client;
+#[cfg(feature = "bevy")]
+pub mod bevy_plugin;
+pub mod cache;
 pub mod client_api;
+pub mod credentials;
 pub mod errors;
 pub mod messages;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod table;
 pub mod web_socket;
 pub mod ws;
 
 pub extern crate spacetimedb;
+// The `TableRow` derive refers to `::spacetime_client_sdk`, so it can be used inside the crate
+extern crate self as spacetime_client_sdk;
@@ -1,10 +1,16 @@
-use crate::client_api::{Message as ApiMessage, Message_oneof_type};
+use crate::client_api::{
+    Event, Event_Status, FunctionCall, Message as ApiMessage, Message_oneof_type, Subscribe,
+    SubscriptionUpdate, TableRowOperation_OperationType,
+};
+use crate::errors::ClientError;
 use crate::web_socket::{ConnectionHandle, NetworkEvent};
-use crate::ws::BuildConnection;
+use crate::ws::{BuildConnection, Protocol};
+use log::warn;
 use protobuf::Message;
 use serde::{Deserialize, Serialize};
-use spacetimedb::spacetimedb_lib::TupleDef;
-use spacetimedb::TypeValue;
+use spacetimedb::spacetimedb_lib::{TupleDef, TupleValue};
+use spacetimedb::{Hash, TypeValue};
+use std::collections::HashMap;
 use tungstenite::Message as WsMessage;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,6 +26,15 @@ impl SpaceDbResponse {
     pub fn to_json(&self) -> String {
         serde_json::to_string(self).unwrap()
     }
+
+    /// The event of the reducer call that caused this message, if any
+    pub fn event(&self) -> Option<&EventJson> {
+        match self {
+            SpaceDbResponse::Event(ev) => Some(ev),
+            SpaceDbResponse::TransactionUpdate(tx) => Some(&tx.event),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,6 +50,11 @@ impl IdentityTokenJson {
             token: token.to_string(),
         }
     }
+
+    /// This is the `identity` of a row, eg: the owner of a player
+    pub fn is(&self, identity: &Hash) -> bool {
+        self.identity.eq_ignore_ascii_case(&to_hex(&identity.data))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -50,12 +70,12 @@ pub struct TableUpdateJson {
     pub table_row_operations: Vec<TableRowOperationJson>,
 }
 
-// #[derive(Debug, Clone, Serialize, Deserialize)]
-// pub struct TableRowOperationJson {
-//     pub op: TableOp,
-//     pub row_pk: String,
-//     pub row: Vec<TypeValue>,
-// }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TableRowOperationJson {
+    pub op: TableOp,
+    pub row_pk: String,
+    pub row: Vec<TypeValue>,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SubscriptionUpdateJson {
@@ -68,7 +88,34 @@ pub struct EventJson {
     pub status: String,          // committed, failed
     pub caller_identity: String, // hex identity
     pub function_call: FunctionCallJson,
+    /// Why the call failed, empty when committed
+    #[serde(default)]
+    pub message: String,
     pub energy_quanta_used: i64,
+    #[serde(default)]
+    pub host_execution_duration_micros: u64,
+}
+
+/// The outcome of a reducer call
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum EventStatus {
+    Committed,
+    Failed,
+    OutOfEnergy,
+}
+
+impl EventJson {
+    pub fn outcome(&self) -> EventStatus {
+        match self.status.as_str() {
+            "committed" => EventStatus::Committed,
+            "out_of_energy" => EventStatus::OutOfEnergy,
+            _ => EventStatus::Failed,
+        }
+    }
+
+    pub fn is_committed(&self) -> bool {
+        self.outcome() == EventStatus::Committed
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -83,6 +130,9 @@ pub struct StmtResultJson {
     pub rows: Vec<Vec<TypeValue>>,
 }
 
+/// The schemas of the tables, keyed by table name.
+pub type TableSchemas = HashMap<String, TupleDef>;
+
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Params {
     entity_id: u64,
@@ -109,125 +159,368 @@ pub enum TableOp {
 pub enum SpaceDbRequest {
     Ping,
     Pong,
-    FunctionCall { name: String, args: Vec<TypeValue> },
+    FunctionCall {
+        name: String,
+        args: Vec<TypeValue>,
+    },
+    /// Replace the subscription with the rows that match the SQL `queries`,
+    /// eg: `SELECT * FROM PlayerComponent WHERE match_id = 1`
+    Subscribe {
+        queries: Vec<String>,
+    },
+}
+
+/// Encode the arguments of a reducer call in the same order they are declared in the module
+pub fn encode_args(args: &[TypeValue]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for arg in args {
+        arg.encode(&mut bytes);
+    }
+    bytes
+}
+
+fn encode_binary(msg: &ApiMessage) -> Result<WsMessage, ClientError> {
+    let bytes = msg
+        .write_to_bytes()
+        .map_err(|err| ClientError::Protocol(format!("failed to encode message: {err}")))?;
+    Ok(WsMessage::Binary(bytes))
 }
 
 pub(crate) fn serialize_msg(
-    _con: &BuildConnection,
+    con: &BuildConnection,
     msg: SpaceDbRequest,
-) -> Option<tungstenite::Message> {
-    //TODO: Should switch protocol from _con, add support for binary
+) -> Result<tungstenite::Message, ClientError> {
     match msg {
-        SpaceDbRequest::FunctionCall { name, args } => {
-            let call = FnCall { name, args };
-            let json = serde_json::to_string(&call).unwrap();
-            println!("{}", &json);
-            Some(WsMessage::Text(json))
-            // let args = make_args(args);
-            //
-            //let mut fun = FunctionCall::new();
-            // fun.set_reducer(name);
-            // fun.set_argBytes(args);
-            // // let mut params = Vec::with_capacity(args.len() + 1);
-            // // params.push(TypeValue::String(name.to_string()));
-            // // params.extend_from_slice(&args);
-            // let mut msg = ApiMessage::new();
-            // msg.set_functionCall(fun);
-            // let mut params = Vec::new();
-            // msg.write_to_vec(&mut params).unwrap();
-            // Some(WsMessage::Binary(params))
+        SpaceDbRequest::FunctionCall { name, args } => match con.protocol {
+            Protocol::Text => {
+                let call = FnCall { name, args };
+                let json = serde_json::to_string(&call)?;
+                Ok(WsMessage::Text(json))
+            }
+            Protocol::Binary => {
+                let mut fun = FunctionCall::new();
+                fun.set_reducer(name);
+                fun.set_argBytes(encode_args(&args));
+
+                let mut msg = ApiMessage::new();
+                msg.set_functionCall(fun);
+                encode_binary(&msg)
+            }
+        },
+        SpaceDbRequest::Subscribe { queries } => match con.protocol {
+            Protocol::Text => {
+                let json = serde_json::json!({ "subscribe": { "query_strings": queries } });
+                Ok(WsMessage::Text(json.to_string()))
+            }
+            Protocol::Binary => {
+                let mut subscribe = Subscribe::new();
+                subscribe.set_query_strings(queries.into());
+
+                let mut msg = ApiMessage::new();
+                msg.set_subscribe(subscribe);
+                encode_binary(&msg)
+            }
+        },
+        SpaceDbRequest::Ping => Ok(WsMessage::Ping(Vec::new())),
+        SpaceDbRequest::Pong => Ok(WsMessage::Pong(Vec::new())),
+    }
+}
+
+/// Hex encoding, as used by the text protocol for identities & primary keys
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Decode the `row` bytes using the table schema registered in [BuildConnection::with_table].
+fn decode_row(
+    con: &BuildConnection,
+    table_name: &str,
+    row: Vec<u8>,
+) -> Result<Vec<TypeValue>, ClientError> {
+    let schema = con.schemas.get(table_name).ok_or_else(|| {
+        ClientError::Decode(format!("no schema registered for table {table_name}"))
+    })?;
+
+    match TupleValue::decode(schema, &row) {
+        (Ok(tuple), _) => Ok(Vec::from(tuple.elements)),
+        (Err(err), _) => Err(ClientError::Decode(format!(
+            "failed to decode row of table {table_name}: {err}"
+        ))),
+    }
+}
+
+fn decode_function_call(call: FunctionCall) -> FunctionCallJson {
+    FunctionCallJson {
+        reducer: call.reducer,
+        arg_bytes: call.argBytes,
+    }
+}
+
+fn decode_event(ev: Event) -> EventJson {
+    let status = match ev.status {
+        Event_Status::committed => "committed",
+        Event_Status::failed => "failed",
+        Event_Status::out_of_energy => "out_of_energy",
+    };
+
+    EventJson {
+        timestamp: ev.timestamp,
+        status: status.to_string(),
+        caller_identity: to_hex(&ev.callerIdentity),
+        function_call: decode_function_call(ev.functionCall.unwrap_or_default()),
+        message: ev.message,
+        energy_quanta_used: ev.energy_quanta_used,
+        host_execution_duration_micros: ev.host_execution_duration_micros,
+    }
+}
+
+fn decode_subscription_update(
+    con: &BuildConnection,
+    update: SubscriptionUpdate,
+) -> Result<SubscriptionUpdateJson, ClientError> {
+    let mut updates = Vec::with_capacity(update.tableUpdates.len());
+
+    for table in update.tableUpdates {
+        let mut ops = Vec::with_capacity(table.tableRowOperations.len());
+
+        for o in table.tableRowOperations {
+            let op = match o.op {
+                TableRowOperation_OperationType::DELETE => TableOp::Delete,
+                TableRowOperation_OperationType::INSERT => TableOp::Insert,
+            };
+
+            ops.push(TableRowOperationJson {
+                op,
+                row_pk: to_hex(&o.row_pk),
+                row: decode_row(con, &table.tableName, o.row)?,
+            })
         }
-        SpaceDbRequest::Ping | SpaceDbRequest::Pong => None,
+
+        updates.push(TableUpdateJson {
+            table_id: table.tableId,
+            table_name: table.tableName,
+            table_row_operations: ops,
+        });
     }
+
+    Ok(SubscriptionUpdateJson {
+        table_updates: updates,
+    })
+}
+
+fn decode_binary(
+    con: &BuildConnection,
+    msg: Message_oneof_type,
+) -> Result<SpaceDbResponse, ClientError> {
+    Ok(match msg {
+        Message_oneof_type::identityToken(token) => SpaceDbResponse::IdentityToken(
+            IdentityTokenJson::new(&to_hex(&token.identity), &token.token),
+        ),
+        Message_oneof_type::subscriptionUpdate(update) => {
+            SpaceDbResponse::SubscriptionUpdate(decode_subscription_update(con, update)?)
+        }
+        Message_oneof_type::transactionUpdate(mut tx) => {
+            SpaceDbResponse::TransactionUpdate(TransactionUpdateJson {
+                event: decode_event(tx.take_event()),
+                subscription_update: decode_subscription_update(con, tx.take_subscriptionUpdate())?,
+            })
+        }
+        Message_oneof_type::event(ev) => SpaceDbResponse::Event(decode_event(ev)),
+        Message_oneof_type::functionCall(call) => {
+            SpaceDbResponse::FunctionCall(decode_function_call(call))
+        }
+        Message_oneof_type::subscribe(_) => {
+            return Err(ClientError::Protocol(
+                "unexpected subscribe message from the server".to_string(),
+            ))
+        }
+    })
 }
 
 pub(crate) fn process_msg(
+    con: &BuildConnection,
     msg: Result<tungstenite::Message, tungstenite::Error>,
 ) -> Option<NetworkEvent> {
-    println!("Received MSG: {:?}", &msg);
-    match msg {
-        Ok(msg) => match msg {
-            WsMessage::Text(txt) => {
-                let handle = ConnectionHandle::new();
-                let msg: SpaceDbResponse = serde_json::from_str(&txt).unwrap();
-                Some(NetworkEvent::Message(handle, msg))
-            }
-            WsMessage::Binary(bin) => {
-                let msg = ApiMessage::parse_from_bytes(&bin).unwrap();
-                println!("Parsed BIN: {:?}", &msg);
-
-                if let Some(msg) = msg.field_type {
-                    let handle = ConnectionHandle::new();
-
-                    Some(NetworkEvent::Message(
-                        handle,
-                        match msg {
-                            Message_oneof_type::identityToken(token) => {
-                                //TODO: Fix &token.identity.to_string()
-                                SpaceDbResponse::IdentityToken(IdentityTokenJson::new(
-                                    "",
-                                    &token.token,
-                                ))
-                            }
-                            Message_oneof_type::subscriptionUpdate(_ev) => {
-                                todo!()
-                                // let mut updates = Vec::with_capacity(ev.tableUpdates.len());
-                                //
-                                // for mut x in ev.tableUpdates {
-                                //     let mut ops = Vec::with_capacity(x.tableRowOperations.len());
-                                //
-                                //     for o in x.tableRowOperations {
-                                //         let op = match o.op {
-                                //             TableRowOperation_OperationType::DELETE => {
-                                //                 TableOp::Delete
-                                //             }
-                                //             TableRowOperation_OperationType::INSERT => {
-                                //                 TableOp::Insert
-                                //             }
-                                //         };
-                                //
-                                //         dbg!(&o.row_pk);
-                                //
-                                //         let row_pk = PrimaryKey::decode(&o.row_pk).unwrap();
-                                //         // let row = TupleValue::decode()
-                                //         dbg!(&row_pk);
-                                //         // ops.push(TableRowOperationJson {
-                                //         //     op,
-                                //         //     row_pk: row_pk.to_string(),
-                                //         //     row: vec![],
-                                //         // })
-                                //     }
-                                //
-                                //     let row = TableUpdateJson {
-                                //         table_id: x.tableId,
-                                //         table_name: x.tableName,
-                                //         table_row_operations: ops,
-                                //     };
-                                //
-                                //     updates.push(row);
-                                // }
-                                //
-                                // let up = SubscriptionUpdateJson {
-                                //     table_updates: updates,
-                                // };
-                                // SpaceDbResponse::SubscriptionUpdate(up)
-                            }
-                            Message_oneof_type::transactionUpdate(_) => return None,
-                            Message_oneof_type::functionCall(_) | Message_oneof_type::event(_) => {
-                                return None
-                            }
-                        },
-                    ))
-                } else {
-                    None
-                }
-            }
-            WsMessage::Ping(_) | WsMessage::Pong(_) | WsMessage::Frame(_) => None,
-            WsMessage::Close(_) => None,
+    let msg = match msg {
+        Ok(WsMessage::Text(txt)) => serde_json::from_str(&txt)
+            .map_err(|err| ClientError::Decode(format!("invalid message: {err}"))),
+        Ok(WsMessage::Binary(bin)) => match ApiMessage::parse_from_bytes(&bin) {
+            Ok(ApiMessage {
+                field_type: Some(msg),
+                ..
+            }) => decode_binary(con, msg),
+            Ok(_) => Err(ClientError::Protocol("message without type".to_string())),
+            Err(err) => Err(ClientError::Protocol(format!("invalid message: {err}"))),
         },
+        Ok(WsMessage::Ping(_) | WsMessage::Pong(_) | WsMessage::Frame(_)) => return None,
+        Ok(WsMessage::Close(_)) => return None,
+        Err(err) => Err(err.into()),
+    };
+
+    Some(match msg {
+        Ok(msg) => NetworkEvent::Message(ConnectionHandle::new(), msg),
         Err(err) => {
-            eprintln!("{}", err);
-            Some(NetworkEvent::Error(None, err.into()))
+            warn!("{err}");
+            NetworkEvent::Error(None, err)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client_api::{TableRowOperation, TableUpdate, TransactionUpdate};
+    use crate::table::schema;
+    use spacetimedb::spacetimedb_lib::TypeDef;
+    use tungstenite::http::Uri;
+
+    fn connection(protocol: Protocol) -> BuildConnection {
+        let url = Uri::from_static("ws://127.0.0.1:3000/database/subscribe?name_or_address=test");
+        BuildConnection::new(url).with_protocol(protocol)
+    }
+
+    fn move_player() -> SpaceDbRequest {
+        SpaceDbRequest::FunctionCall {
+            name: "move_player".to_string(),
+            args: vec![TypeValue::U64(1), TypeValue::U8(4)],
+        }
+    }
+
+    #[test]
+    fn test_serialize_text() {
+        let msg = serialize_msg(&connection(Protocol::Text), move_player()).unwrap();
+        assert!(matches!(msg, WsMessage::Text(_)));
+    }
+
+    #[test]
+    fn test_serialize_binary() {
+        let msg = serialize_msg(&connection(Protocol::Binary), move_player()).unwrap();
+        let bin = match msg {
+            WsMessage::Binary(bin) => bin,
+            x => panic!("Expected a binary message, got {x:?}"),
+        };
+
+        let msg = ApiMessage::parse_from_bytes(&bin).unwrap();
+        let call = msg.get_functionCall();
+        assert_eq!(call.get_reducer(), "move_player");
+        assert_eq!(
+            call.get_argBytes(),
+            encode_args(&[TypeValue::U64(1), TypeValue::U8(4)])
+        );
+    }
+
+    #[test]
+    fn test_serialize_subscribe() {
+        let queries = vec!["SELECT * FROM PlayerComponent".to_string()];
+
+        let msg = serialize_msg(
+            &connection(Protocol::Text),
+            SpaceDbRequest::Subscribe {
+                queries: queries.clone(),
+            },
+        );
+        assert_eq!(
+            msg.unwrap(),
+            WsMessage::Text(
+                r#"{"subscribe":{"query_strings":["SELECT * FROM PlayerComponent"]}}"#.to_string()
+            )
+        );
+
+        let msg = serialize_msg(
+            &connection(Protocol::Binary),
+            SpaceDbRequest::Subscribe {
+                queries: queries.clone(),
+            },
+        );
+        let bin = match msg.unwrap() {
+            WsMessage::Binary(bin) => bin,
+            x => panic!("Expected a binary message, got {x:?}"),
+        };
+        let msg = ApiMessage::parse_from_bytes(&bin).unwrap();
+        assert_eq!(msg.get_subscribe().get_query_strings(), queries.as_slice());
+    }
+
+    /// A binary transaction update, with an insert of `[1, 2, 3]` into `PlayerComponent`
+    fn binary_transaction() -> WsMessage {
+        let mut op = TableRowOperation::new();
+        op.set_op(TableRowOperation_OperationType::INSERT);
+        op.set_row_pk(vec![0xab, 0x01]);
+        op.set_row(vec![1, 2, 3]);
+
+        let mut table = TableUpdate::new();
+        table.set_tableId(1);
+        table.set_tableName("PlayerComponent".to_string());
+        table.mut_tableRowOperations().push(op);
+
+        let mut update = SubscriptionUpdate::new();
+        update.mut_tableUpdates().push(table);
+
+        let mut ev = Event::new();
+        ev.set_status(Event_Status::failed);
+        ev.set_callerIdentity(vec![0x0f]);
+
+        let mut tx = TransactionUpdate::new();
+        tx.set_event(ev);
+        tx.set_subscriptionUpdate(update);
+
+        let mut msg = ApiMessage::new();
+        msg.set_transactionUpdate(tx);
+        WsMessage::Binary(msg.write_to_bytes().unwrap())
+    }
+
+    #[test]
+    fn test_process_binary_transaction() {
+        let con = connection(Protocol::Binary).with_table(
+            "PlayerComponent",
+            schema(
+                "PlayerComponent",
+                vec![("a", TypeDef::U8), ("b", TypeDef::U8), ("c", TypeDef::U8)],
+            ),
+        );
+
+        let tx = match process_msg(&con, Ok(binary_transaction())) {
+            Some(NetworkEvent::Message(_, SpaceDbResponse::TransactionUpdate(tx))) => tx,
+            x => panic!("Expected a transaction update, got {x:?}"),
+        };
+
+        assert_eq!(tx.event.status, "failed");
+        assert_eq!(tx.event.caller_identity, "0f");
+        let table = &tx.subscription_update.table_updates[0];
+        assert_eq!(table.table_name, "PlayerComponent");
+        let row = &table.table_row_operations[0];
+        assert_eq!(row.op, TableOp::Insert);
+        assert_eq!(row.row_pk, "ab01");
+        assert_eq!(
+            row.row,
+            vec![TypeValue::U8(1), TypeValue::U8(2), TypeValue::U8(3)]
+        );
+    }
+
+    #[test]
+    fn test_process_unknown_table() {
+        let msg = process_msg(&connection(Protocol::Binary), Ok(binary_transaction()));
+        match msg {
+            Some(NetworkEvent::Error(None, ClientError::Decode(err))) => {
+                assert!(err.contains("PlayerComponent"), "{err}")
+            }
+            x => panic!("Expected a decode error, got {x:?}"),
         }
     }
+
+    #[test]
+    fn test_process_malformed() {
+        let msg = process_msg(&connection(Protocol::Text), Ok(WsMessage::Text("{".into())));
+        assert!(matches!(
+            msg,
+            Some(NetworkEvent::Error(None, ClientError::Decode(_)))
+        ));
+
+        // A field of 255 bytes, truncated
+        let bin = WsMessage::Binary(vec![0x0a, 0xff]);
+        let msg = process_msg(&connection(Protocol::Binary), Ok(bin));
+        assert!(matches!(
+            msg,
+            Some(NetworkEvent::Error(None, ClientError::Protocol(_)))
+        ));
+    }
 }
@@ -0,0 +1,468 @@
+//! An in-process mock of the SpacetimeDB server, to test the clients offline.
+//!
+//! It speaks the `v1.text.spacetimedb` & `v1.bin.spacetimedb` subprotocols, returns the
+//! identity headers and sends the updates scripted with [MockServer::send]:
+//!
+//! ```ignore
+//! let server = MockServer::start().await?;
+//! let mut client = AsyncClient::new(BuildConnection::new(server.url("game")?));
+//! let events = client.connect().await?;
+//! server.send(SpaceDbResponse::TransactionUpdate(tx));
+//! ```
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::client_api::{
+    Event, Event_Status, FunctionCall, IdentityToken, Message as ApiMessage, SubscriptionUpdate,
+    TableRowOperation, TableRowOperation_OperationType, TableUpdate, TransactionUpdate,
+};
+use crate::errors::ClientError;
+use crate::messages::{
+    encode_args, to_hex, EventJson, EventStatus, FunctionCallJson, IdentityTokenJson,
+    SpaceDbResponse, SubscriptionUpdateJson, TableOp,
+};
+use crate::ws::Protocol;
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine as _;
+use futures::{future, SinkExt, StreamExt};
+use log::{info, warn};
+use protobuf::Message;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::accept_hdr_async;
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::header::{AUTHORIZATION, SEC_WEBSOCKET_PROTOCOL};
+use tungstenite::http::{HeaderValue, StatusCode, Uri};
+use tungstenite::Message as WsMessage;
+use uuid::Uuid;
+
+/// The state shared with the connections of the server
+struct State {
+    clients: Mutex<Vec<(Protocol, UnboundedSender<WsMessage>)>>,
+    received: Mutex<Vec<WsMessage>>,
+    snapshot: Mutex<Option<SubscriptionUpdateJson>>,
+    /// Set by [MockServer::hang]
+    hung: watch::Sender<bool>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            clients: Default::default(),
+            received: Default::default(),
+            snapshot: Default::default(),
+            hung: watch::channel(false).0,
+        }
+    }
+}
+
+impl State {
+    fn clients(&self) -> MutexGuard<Vec<(Protocol, UnboundedSender<WsMessage>)>> {
+        self.clients.lock().expect("failed to lock the clients")
+    }
+}
+
+pub struct MockServer {
+    addr: SocketAddr,
+    identity: IdentityTokenJson,
+    state: Arc<State>,
+    handle: JoinHandle<()>,
+}
+
+impl MockServer {
+    /// Listen in a free port of localhost, in the current tokio runtime
+    pub async fn start() -> Result<Self, ClientError> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let mut identity = Vec::with_capacity(32);
+        identity.extend_from_slice(Uuid::new_v4().as_bytes());
+        identity.extend_from_slice(Uuid::new_v4().as_bytes());
+        let identity = IdentityTokenJson::new(&to_hex(&identity), "mock-token");
+        let state = Arc::new(State::default());
+
+        let handle = tokio::spawn(accept(listener, identity.clone(), state.clone()));
+        info!("Mock server listening on: {addr}");
+
+        Ok(Self {
+            addr,
+            identity,
+            state,
+            handle,
+        })
+    }
+
+    /// The `host:port` of the server
+    pub fn host(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// The url to subscribe to the database `name_or_address`
+    pub fn url(&self, name_or_address: &str) -> Result<Uri, ClientError> {
+        let url = format!(
+            "ws://{}/database/subscribe?name_or_address={name_or_address}",
+            self.addr
+        );
+        Ok(Uri::from_str(&url)?)
+    }
+
+    /// The identity given to all the clients. A connection with another token is rejected
+    pub fn identity(&self) -> &IdentityTokenJson {
+        &self.identity
+    }
+
+    /// The rows sent when a client connects or subscribes
+    pub fn set_snapshot(&self, snapshot: SubscriptionUpdateJson) {
+        *self
+            .state
+            .snapshot
+            .lock()
+            .expect("failed to lock the snapshot") = Some(snapshot);
+    }
+
+    /// Send `msg` to all the connected clients, encoded with the protocol of each one
+    pub fn send(&self, msg: SpaceDbResponse) {
+        self.state
+            .clients()
+            .retain(|(protocol, tx)| tx.send(encode(*protocol, &msg)).is_ok());
+    }
+
+    /// Drop the connection of all the clients, without a close frame
+    pub fn disconnect_all(&self) {
+        self.state.clients().clear();
+    }
+
+    /// Stop answering the clients, even the pings, but keep their connection open. Like a
+    /// server that died without closing the sockets
+    pub fn hang(&self) {
+        self.state.hung.send_replace(true);
+    }
+
+    /// The number of clients connected
+    pub fn connected(&self) -> usize {
+        self.state
+            .clients()
+            .iter()
+            .filter(|(_, tx)| !tx.is_closed())
+            .count()
+    }
+
+    /// The messages received from the clients, in order
+    pub fn received(&self) -> Vec<WsMessage> {
+        self.state
+            .received
+            .lock()
+            .expect("failed to lock the received messages")
+            .clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+async fn accept(listener: TcpListener, identity: IdentityTokenJson, state: Arc<State>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(connection(stream, identity.clone(), state.clone()));
+            }
+            Err(err) => warn!("Mock server failed to accept: {err}"),
+        }
+    }
+}
+
+async fn connection(stream: TcpStream, identity: IdentityTokenJson, state: Arc<State>) {
+    let mut protocol = Protocol::Text;
+    let token = format!(
+        "Basic {}",
+        BASE64_STANDARD.encode(format!("token:{}", identity.token))
+    );
+    let handshake = |req: &Request, mut resp: Response| -> Result<Response, ErrorResponse> {
+        if matches!(req.headers().get(AUTHORIZATION), Some(x) if x != token.as_str()) {
+            let mut err = ErrorResponse::new(Some("Invalid token".to_string()));
+            *err.status_mut() = StatusCode::UNAUTHORIZED;
+            return Err(err);
+        }
+        if let Some(requested) = req.headers().get(SEC_WEBSOCKET_PROTOCOL) {
+            if requested == "v1.bin.spacetimedb" {
+                protocol = Protocol::Binary;
+            }
+            resp.headers_mut()
+                .insert(SEC_WEBSOCKET_PROTOCOL, requested.clone());
+        }
+        let headers = resp.headers_mut();
+        if let Ok(x) = HeaderValue::from_str(&identity.identity) {
+            headers.insert("spacetime-identity", x);
+        }
+        if let Ok(x) = HeaderValue::from_str(&identity.token) {
+            headers.insert("spacetime-identity-token", x);
+        }
+        Ok(resp)
+    };
+
+    let ws_stream = match accept_hdr_async(stream, handshake).await {
+        Ok(x) => x,
+        Err(err) => {
+            warn!("Mock server failed the handshake: {err}");
+            return;
+        }
+    };
+    let (mut write, mut read) = ws_stream.split();
+    let (tx, mut rx) = unbounded_channel();
+
+    let _ = tx.send(encode(protocol, &SpaceDbResponse::IdentityToken(identity)));
+    if let Some(snapshot) = snapshot(&state, protocol) {
+        let _ = tx.send(snapshot);
+    }
+    // The only sender, so the connection is dropped with it by `disconnect_all`
+    state.clients().push((protocol, tx));
+    let hung = hung(state.hung.subscribe());
+    tokio::pin!(hung);
+
+    loop {
+        tokio::select! {
+            _ = &mut hung => {
+                // Not reading anymore, so the pings are not answered
+                return future::pending().await;
+            }
+            msg = read.next() => match msg {
+                Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(msg)) => {
+                    let subscribe = is_subscribe(protocol, &msg);
+                    state.received.lock().expect("failed to lock the received messages").push(msg);
+                    if let (true, Some(snapshot)) = (subscribe, snapshot(&state, protocol)) {
+                        if write.send(snapshot).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            },
+            msg = rx.recv() => match msg {
+                Some(msg) => {
+                    if write.send(msg).await.is_err() {
+                        return;
+                    }
+                }
+                None => return,
+            },
+        }
+    }
+}
+
+/// Resolve once the server hangs
+async fn hung(mut rx: watch::Receiver<bool>) {
+    while !*rx.borrow_and_update() {
+        if rx.changed().await.is_err() {
+            return future::pending().await;
+        }
+    }
+}
+
+fn snapshot(state: &State, protocol: Protocol) -> Option<WsMessage> {
+    let snapshot = state.snapshot.lock().expect("failed to lock the snapshot");
+    let snapshot = SpaceDbResponse::SubscriptionUpdate(snapshot.clone()?);
+    Some(encode(protocol, &snapshot))
+}
+
+fn is_subscribe(protocol: Protocol, msg: &WsMessage) -> bool {
+    match (protocol, msg) {
+        (Protocol::Text, WsMessage::Text(txt)) => txt.starts_with(r#"{"subscribe""#),
+        (Protocol::Binary, WsMessage::Binary(bin)) => ApiMessage::parse_from_bytes(bin)
+            .map(|x| x.has_subscribe())
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// Encode `msg` as the server does
+fn encode(protocol: Protocol, msg: &SpaceDbResponse) -> WsMessage {
+    match protocol {
+        Protocol::Text => WsMessage::Text(msg.to_json()),
+        Protocol::Binary => {
+            let msg = encode_binary(msg);
+            WsMessage::Binary(msg.write_to_bytes().expect("failed to encode the message"))
+        }
+    }
+}
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .filter_map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn encode_function_call(call: &FunctionCallJson) -> FunctionCall {
+    let mut x = FunctionCall::new();
+    x.set_reducer(call.reducer.clone());
+    x.set_argBytes(call.arg_bytes.clone());
+    x
+}
+
+fn encode_event(ev: &EventJson) -> Event {
+    let mut x = Event::new();
+    x.set_timestamp(ev.timestamp);
+    x.set_status(match ev.outcome() {
+        EventStatus::Committed => Event_Status::committed,
+        EventStatus::Failed => Event_Status::failed,
+        EventStatus::OutOfEnergy => Event_Status::out_of_energy,
+    });
+    x.set_callerIdentity(from_hex(&ev.caller_identity));
+    x.set_functionCall(encode_function_call(&ev.function_call));
+    x.set_message(ev.message.clone());
+    x.set_energy_quanta_used(ev.energy_quanta_used);
+    x.set_host_execution_duration_micros(ev.host_execution_duration_micros);
+    x
+}
+
+fn encode_subscription_update(update: &SubscriptionUpdateJson) -> SubscriptionUpdate {
+    let mut x = SubscriptionUpdate::new();
+    for table in &update.table_updates {
+        let mut t = TableUpdate::new();
+        t.set_tableId(table.table_id);
+        t.set_tableName(table.table_name.clone());
+        for row in &table.table_row_operations {
+            let mut op = TableRowOperation::new();
+            op.set_op(match row.op {
+                TableOp::Delete => TableRowOperation_OperationType::DELETE,
+                TableOp::Insert | TableOp::Update => TableRowOperation_OperationType::INSERT,
+            });
+            op.set_row_pk(from_hex(&row.row_pk));
+            op.set_row(encode_args(&row.row));
+            t.mut_tableRowOperations().push(op);
+        }
+        x.mut_tableUpdates().push(t);
+    }
+    x
+}
+
+fn encode_binary(msg: &SpaceDbResponse) -> ApiMessage {
+    let mut x = ApiMessage::new();
+    match msg {
+        SpaceDbResponse::FunctionCall(call) => x.set_functionCall(encode_function_call(call)),
+        SpaceDbResponse::SubscriptionUpdate(update) => {
+            x.set_subscriptionUpdate(encode_subscription_update(update))
+        }
+        SpaceDbResponse::Event(ev) => x.set_event(encode_event(ev)),
+        SpaceDbResponse::TransactionUpdate(tx) => {
+            let mut update = TransactionUpdate::new();
+            update.set_event(encode_event(&tx.event));
+            update.set_subscriptionUpdate(encode_subscription_update(&tx.subscription_update));
+            x.set_transactionUpdate(update)
+        }
+        SpaceDbResponse::IdentityToken(token) => {
+            let mut identity = IdentityToken::new();
+            identity.set_identity(from_hex(&token.identity));
+            identity.set_token(token.token.clone());
+            x.set_identityToken(identity)
+        }
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::async_client::AsyncClient;
+    use crate::messages::{TableRowOperationJson, TableUpdateJson, TransactionUpdateJson};
+    use crate::table::schema;
+    use crate::web_socket::NetworkEvent;
+    use crate::ws::BuildConnection;
+    use spacetimedb::spacetimedb_lib::TypeDef;
+    use spacetimedb::TypeValue;
+
+    fn players(entity_ids: &[u64]) -> SubscriptionUpdateJson {
+        SubscriptionUpdateJson {
+            table_updates: vec![TableUpdateJson {
+                table_id: 1,
+                table_name: "PlayerComponent".to_string(),
+                table_row_operations: entity_ids
+                    .iter()
+                    .map(|x| TableRowOperationJson {
+                        op: TableOp::Insert,
+                        row_pk: format!("{x:02x}"),
+                        row: vec![TypeValue::U64(*x), TypeValue::U8(0)],
+                    })
+                    .collect(),
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_binary_updates() {
+        let server = MockServer::start().await.unwrap();
+        server.set_snapshot(players(&[0]));
+
+        let con = BuildConnection::new(server.url("test").unwrap())
+            .with_protocol(Protocol::Binary)
+            .with_table(
+                "PlayerComponent",
+                schema(
+                    "PlayerComponent",
+                    vec![("entity_id", TypeDef::U64), ("input", TypeDef::U8)],
+                ),
+            );
+        let mut client = AsyncClient::new(con);
+        let mut events = client.connect().await.unwrap();
+        assert_eq!(
+            client.identity().unwrap().identity,
+            server.identity().identity
+        );
+
+        assert!(matches!(
+            events.next().await,
+            Some(NetworkEvent::Connected(_))
+        ));
+        match events.next().await {
+            Some(NetworkEvent::Message(_, SpaceDbResponse::IdentityToken(x))) => {
+                assert_eq!(x.identity, server.identity().identity)
+            }
+            x => panic!("Expected the identity, got {x:?}"),
+        }
+        assert!(matches!(
+            events.next().await,
+            Some(NetworkEvent::Message(
+                _,
+                SpaceDbResponse::SubscriptionUpdate(_)
+            ))
+        ));
+
+        let call = client.call_reducer("create_new_player", vec![]).unwrap();
+        let ev = EventJson {
+            timestamp: 0,
+            status: "committed".to_string(),
+            caller_identity: server.identity().identity.clone(),
+            function_call: FunctionCallJson {
+                reducer: "create_new_player".to_string(),
+                arg_bytes: Vec::new(),
+            },
+            message: String::new(),
+            energy_quanta_used: 0,
+            host_execution_duration_micros: 10,
+        };
+        server.send(SpaceDbResponse::TransactionUpdate(TransactionUpdateJson {
+            event: ev,
+            subscription_update: players(&[1]),
+        }));
+
+        assert!(matches!(
+            events.next().await,
+            Some(NetworkEvent::Message(
+                _,
+                SpaceDbResponse::TransactionUpdate(_)
+            ))
+        ));
+        let ev = call.await.unwrap();
+        assert!(ev.is_committed());
+        assert_eq!(ev.host_execution_duration_micros, 10);
+
+        let table = client.cache();
+        let table = table.table("PlayerComponent").unwrap();
+        assert_eq!(table.len(), 2);
+    }
+}
@@ -0,0 +1,300 @@
+//! Typed access to the rows of the tables.
+//!
+//! Use `#[derive(TableRow)]` to map the rows of a table into a struct, by column name:
+//!
+//! ```ignore
+//! #[derive(Debug, TableRow)]
+//! struct PlayerComponent {
+//!     #[primary_key]
+//!     entity_id: u64,
+//!     owner_id: Hash,
+//!     input: u8,
+//! }
+//! ```
+use crate::errors::ClientError;
+use crate::messages::{StmtResultJson, TableOp, TableRowOperationJson, TableUpdateJson};
+use spacetimedb::spacetimedb_lib::{ElementDef, TupleDef, TypeDef};
+use spacetimedb::{Hash, TypeValue};
+
+pub use spacetime_client_sdk_derive::TableRow;
+
+/// A row of a table, decoded into a Rust type
+pub trait TableRow: Sized {
+    /// The name of the table in the module
+    fn table_name() -> &'static str;
+    /// The schema of the table, used when none was registered with
+    /// [crate::ws::BuildConnection::with_table]
+    fn schema() -> TupleDef;
+    /// The column that identify the row across updates, if any
+    fn primary_key() -> Option<&'static str> {
+        None
+    }
+    fn from_columns(columns: &Columns) -> Result<Self, ClientError>;
+}
+
+/// A value that can be stored in a column
+pub trait Column: Sized {
+    fn type_def() -> TypeDef;
+    fn from_value(value: &TypeValue) -> Result<Self, ClientError>;
+}
+
+/// Build the [TupleDef] of a table from its `(column, type)` pairs
+pub fn schema(table_name: &str, columns: Vec<(&str, TypeDef)>) -> TupleDef {
+    TupleDef {
+        name: Some(table_name.to_string()),
+        elements: columns
+            .into_iter()
+            .enumerate()
+            .map(|(tag, (name, element_type))| ElementDef {
+                tag: tag as u8,
+                name: Some(name.to_string()),
+                element_type,
+            })
+            .collect(),
+    }
+}
+
+/// The values of a row, addressable by the column names of the schema
+pub struct Columns<'a> {
+    schema: &'a TupleDef,
+    row: &'a [TypeValue],
+}
+
+impl<'a> Columns<'a> {
+    pub fn new(schema: &'a TupleDef, row: &'a [TypeValue]) -> Self {
+        Self { schema, row }
+    }
+
+    pub fn get<T: Column>(&self, name: &str) -> Result<T, ClientError> {
+        let pos = self
+            .schema
+            .elements
+            .iter()
+            .position(|x| x.name.as_deref() == Some(name))
+            .ok_or_else(|| ClientError::Decode(format!("Column `{name}` not in the schema")))?;
+
+        let value = self
+            .row
+            .get(pos)
+            .ok_or_else(|| ClientError::Decode(format!("Column `{name}` not in the row")))?;
+
+        T::from_value(value).map_err(|err| ClientError::Decode(format!("Column `{name}`: {err}")))
+    }
+}
+
+impl TableRowOperationJson {
+    /// Decode the row, looking up the columns by name in `schema`
+    pub fn decode<T: TableRow>(&self, schema: &TupleDef) -> Result<T, ClientError> {
+        T::from_columns(&Columns::new(schema, &self.row))
+    }
+}
+
+impl TableUpdateJson {
+    /// Decode the rows of the table `T`. Returns nothing if this update is for another table
+    pub fn decode<T: TableRow>(&self, schema: &TupleDef) -> Result<Vec<(TableOp, T)>, ClientError> {
+        if self.table_name != T::table_name() {
+            return Ok(Vec::new());
+        }
+
+        self.table_row_operations
+            .iter()
+            .map(|row| Ok((row.op, row.decode(schema)?)))
+            .collect()
+    }
+}
+
+impl StmtResultJson {
+    /// Decode the rows of the result, looking up the columns by name in its schema
+    pub fn decode<T: TableRow>(&self) -> Result<Vec<T>, ClientError> {
+        self.rows
+            .iter()
+            .map(|row| T::from_columns(&Columns::new(&self.schema, row)))
+            .collect()
+    }
+}
+
+fn invalid(expected: &str, value: &TypeValue) -> ClientError {
+    ClientError::Decode(format!("Expected {expected}, got {value:?}"))
+}
+
+// The text protocol don't carry the type of the numbers, so any integer that fits is accepted
+macro_rules! int_column {
+    ($ty:ty, $def:ident) => {
+        impl Column for $ty {
+            fn type_def() -> TypeDef {
+                TypeDef::$def
+            }
+
+            fn from_value(value: &TypeValue) -> Result<Self, ClientError> {
+                let x = match value {
+                    TypeValue::I8(x) => <$ty>::try_from(*x).ok(),
+                    TypeValue::U8(x) => <$ty>::try_from(*x).ok(),
+                    TypeValue::I16(x) => <$ty>::try_from(*x).ok(),
+                    TypeValue::U16(x) => <$ty>::try_from(*x).ok(),
+                    TypeValue::I32(x) => <$ty>::try_from(*x).ok(),
+                    TypeValue::U32(x) => <$ty>::try_from(*x).ok(),
+                    TypeValue::I64(x) => <$ty>::try_from(*x).ok(),
+                    TypeValue::U64(x) => <$ty>::try_from(*x).ok(),
+                    TypeValue::I128(x) => <$ty>::try_from(*x).ok(),
+                    TypeValue::U128(x) => <$ty>::try_from(*x).ok(),
+                    _ => None,
+                };
+                x.ok_or_else(|| invalid(stringify!($ty), value))
+            }
+        }
+    };
+}
+
+int_column!(i8, I8);
+int_column!(u8, U8);
+int_column!(i16, I16);
+int_column!(u16, U16);
+int_column!(i32, I32);
+int_column!(u32, U32);
+int_column!(i64, I64);
+int_column!(u64, U64);
+int_column!(i128, I128);
+int_column!(u128, U128);
+
+impl Column for bool {
+    fn type_def() -> TypeDef {
+        TypeDef::Bool
+    }
+
+    fn from_value(value: &TypeValue) -> Result<Self, ClientError> {
+        match value {
+            TypeValue::Bool(x) => Ok(*x),
+            x => Err(invalid("bool", x)),
+        }
+    }
+}
+
+impl Column for f32 {
+    fn type_def() -> TypeDef {
+        TypeDef::F32
+    }
+
+    fn from_value(value: &TypeValue) -> Result<Self, ClientError> {
+        match value {
+            TypeValue::F32(x) => Ok((*x).into()),
+            // The text protocol decode the numbers as the widest float
+            TypeValue::F64(x) => Ok(f64::from(*x) as f32),
+            x => Err(invalid("f32", x)),
+        }
+    }
+}
+
+impl Column for String {
+    fn type_def() -> TypeDef {
+        TypeDef::String
+    }
+
+    fn from_value(value: &TypeValue) -> Result<Self, ClientError> {
+        match value {
+            TypeValue::String(x) => Ok(x.clone()),
+            x => Err(invalid("String", x)),
+        }
+    }
+}
+
+impl Column for Vec<u8> {
+    fn type_def() -> TypeDef {
+        TypeDef::Bytes
+    }
+
+    fn from_value(value: &TypeValue) -> Result<Self, ClientError> {
+        match value {
+            TypeValue::Bytes(x) => Ok(x.clone()),
+            x => Err(invalid("bytes", x)),
+        }
+    }
+}
+
+impl Column for Hash {
+    fn type_def() -> TypeDef {
+        TypeDef::Bytes
+    }
+
+    fn from_value(value: &TypeValue) -> Result<Self, ClientError> {
+        match value {
+            TypeValue::Bytes(x) => Ok(Hash::from_slice(x)),
+            x => Err(invalid("identity", x)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_columns_by_name() {
+        let schema = schema(
+            "PlayerComponent",
+            vec![("entity_id", TypeDef::U64), ("input", TypeDef::U8)],
+        );
+        // The text protocol decode small numbers as the smaller type that fit
+        let row = [TypeValue::I8(1), TypeValue::U8(4)];
+        let columns = Columns::new(&schema, &row);
+
+        assert_eq!(columns.get::<u8>("input").unwrap(), 4);
+        assert_eq!(columns.get::<u64>("entity_id").unwrap(), 1);
+        assert!(columns.get::<u64>("owner_id").is_err());
+        assert!(columns.get::<String>("input").is_err());
+    }
+
+    #[derive(Debug, PartialEq, TableRow)]
+    #[table_row(name = "PlayerComponent")]
+    struct Player {
+        #[primary_key]
+        entity_id: u64,
+        input: u8,
+        name: String,
+    }
+
+    #[test]
+    fn test_derive() {
+        assert_eq!(Player::table_name(), "PlayerComponent");
+        assert_eq!(Player::primary_key(), Some("entity_id"));
+
+        let schema = Player::schema();
+        assert_eq!(
+            schema,
+            super::schema(
+                "PlayerComponent",
+                vec![
+                    ("entity_id", TypeDef::U64),
+                    ("input", TypeDef::U8),
+                    ("name", TypeDef::String),
+                ],
+            )
+        );
+
+        // The columns are found by name, not by position
+        let schema = super::schema(
+            "PlayerComponent",
+            vec![
+                ("name", TypeDef::String),
+                ("input", TypeDef::U8),
+                ("entity_id", TypeDef::U64),
+            ],
+        );
+        let row = [
+            TypeValue::String("Alice".to_string()),
+            TypeValue::U8(4),
+            TypeValue::U64(1),
+        ];
+        let player = Player::from_columns(&Columns::new(&schema, &row)).unwrap();
+        assert_eq!(
+            player,
+            Player {
+                entity_id: 1,
+                input: 4,
+                name: "Alice".to_string(),
+            }
+        );
+
+        let row = [TypeValue::String("Alice".to_string()), TypeValue::U8(4)];
+        assert!(Player::from_columns(&Columns::new(&schema, &row)).is_err());
+    }
+}
@@ -1,17 +1,21 @@
-use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, MutexGuard};
+use std::time::Duration;
 
+use crate::async_client::{AsyncClient, ReducerCall};
+use crate::cache::ClientCache;
+use crate::credentials::Credentials;
 use crate::errors::ClientError;
 use crate::messages::{
-    process_msg, serialize_msg, IdentityTokenJson, SpaceDbRequest, SpaceDbResponse,
+    IdentityTokenJson, SpaceDbRequest, SpaceDbResponse, StmtResultJson, TableOp, TableUpdateJson,
 };
-use crate::ws::{build_req, BuildConnection};
+use crate::table::TableRow;
+use crate::ws::BuildConnection;
 use crossbeam_channel::{unbounded, Receiver, TryRecvError};
-use futures::{SinkExt, StreamExt};
-use log::{error, info, warn};
+use futures::StreamExt;
+use log::warn;
+use spacetimedb::TypeValue;
+use tokio::time::timeout;
 use tokio::{runtime::Runtime, task::JoinHandle};
-use tokio_tungstenite::connect_async;
-use tungstenite::http::Uri;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Default, Eq, PartialEq)]
@@ -34,166 +38,127 @@ impl ConnectionHandle {
 #[derive(Debug)]
 pub enum NetworkEvent {
     Connected(ConnectionHandle),
+    /// The connection was lost and is reestablished with the same identity
+    Reconnected(ConnectionHandle),
     Disconnected(ConnectionHandle),
     Message(ConnectionHandle, SpaceDbResponse),
     Error(Option<ConnectionHandle>, ClientError),
 }
 
+/// A blocking client, that runs an [AsyncClient] in its own tokio runtime.
+///
+/// The events are polled without blocking with [Client::try_recv], eg: once per frame.
 pub struct Client {
     rt: Arc<Runtime>,
+    inner: AsyncClient,
     handle: Option<JoinHandle<()>>,
     rx: Option<Arc<Receiver<NetworkEvent>>>,
-    tx: Option<Arc<tokio::sync::mpsc::UnboundedSender<tungstenite::Message>>>,
-    con: BuildConnection,
 }
 
 impl Client {
+    /// Connect to the database `name_or_address` of the server at `host`, see
+    /// [BuildConnection::for_database]
     pub fn new(host: &str, name_or_address: &str) -> Result<Self, ClientError> {
-        let url = format!("ws://{host}/database/subscribe?name_or_address={name_or_address}");
-        let url = Uri::from_str(&url)?;
-        let con = BuildConnection::new(url);
+        Self::with_connection(BuildConnection::for_database(host, name_or_address)?)
+    }
 
+    /// Build a client from a prepared [BuildConnection], eg: to select the [crate::ws::Protocol]
+    pub fn with_connection(con: BuildConnection) -> Result<Self, ClientError> {
         Ok(Client {
             rt: Arc::new(
                 tokio::runtime::Builder::new_multi_thread()
                     .enable_all()
                     .build()?,
             ),
+            inner: AsyncClient::new(con),
             handle: None,
             rx: None,
-            tx: None,
-            con,
         })
     }
 
-    pub fn is_running(&self) -> bool {
-        self.handle.is_some() && self.rx.is_some() && self.tx.is_some()
+    /// Reuse the credentials saved in `store`, so the client keep the same identity between
+    /// runs. When there are none, or the server rejects them, the new ones are saved on
+    /// [Self::connect].
+    pub fn with_credentials(mut self, store: impl Credentials + Send + Sync + 'static) -> Self {
+        self.inner.set_credentials(store);
+        self
     }
 
-    fn login(&mut self) -> Result<BuildConnection, ClientError> {
-        let con = self.con.clone();
-
-        info!("Login to: {}...", &con.url);
-        let request = build_req(&self.con).body(())?;
-        let event_loop = async move {
-            match connect_async(request).await {
-                Ok((_, response)) => {
-                    info!("Logged into: {} DONE", con.url);
-                    //dbg!(&response);
-                    let token = response
-                        .headers()
-                        .get("spacetime-identity-token")
-                        .map(|x| x.to_str());
-                    let identity = response
-                        .headers()
-                        .get("spacetime-identity")
-                        .map(|x| x.to_str());
-
-                    match (token, identity) {
-                        (Some(Ok(token)), Some(Ok(identity))) => {
-                            let t = IdentityTokenJson::new(identity, token);
-                            Ok(con.with_auth(t))
-                        }
-                        _ => {
-                            warn!("Response not return auth headers");
-                            Err(ClientError::AuthFailed)
-                        }
-                    }
-                }
-                Err(err) => Err(err.into()),
-            }
-        };
+    /// The identity of the client, known after [Self::connect]
+    pub fn identity(&self) -> Option<&IdentityTokenJson> {
+        self.inner.identity()
+    }
 
-        self.rt.block_on(event_loop)
+    /// The round-trip time to the server, see [AsyncClient::latency]
+    pub fn latency(&self) -> Option<Duration> {
+        self.inner.latency()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.inner.is_running() && self.handle.is_some() && self.rx.is_some()
     }
 
     pub fn connect(&mut self) -> Result<(), ClientError> {
-        let con = self.login()?;
-        //let con = self.con.clone();
+        let mut events = self.rt.block_on(self.inner.connect())?;
         let (ev_tx, ev_rx) = unbounded();
-        let (from_handler_tx, mut from_handler_rx) = tokio::sync::mpsc::unbounded_channel();
-        let url = con.url.clone();
-        let request = build_req(&con).body(())?;
-        info!("Connecting to: {}...", &url);
-
-        let event_loop = async move {
-            let (ws_stream, _) = match connect_async(request).await {
-                Ok(x) => x,
-                Err(err) => {
-                    ev_tx
-                        .send(NetworkEvent::Error(None, ClientError::Tungstenite(err)))
-                        .expect("failed to send error network event");
-                    return;
-                }
-            };
-            info!("Connected to: {}...", &url);
-            //dbg!(response);
-            let (mut write, mut read) = ws_stream.split();
-            ev_tx
-                .send(NetworkEvent::Connected(ConnectionHandle::new()))
-                .expect("failed to send network event");
-
-            loop {
-                tokio::select! {
-                    //Receive messages from the websocket
-                    msg = read.next() => {
-                        if let Some(msg) = msg {
-                            if let Some(msg) = process_msg(msg) {
-                                ev_tx.send(msg).expect("failed to forward network message");
-                            }
-                        }
-                    }
-                    //Receive messages from the game
-                    game_msg = from_handler_rx.recv() => {
-                        match game_msg {
-                            None => {
-                                warn!("failed to forward message to sink");
-                            }
-                            Some(ev) => {
-                                if let Err(e) = write.send(ev).await {
-                                    error!("failed to send message to server: {}", e);
-                                }
-                            }
-                        }
-                    }
+
+        // Forward the events to the game, that poll them with `try_recv`
+        let forward = async move {
+            while let Some(ev) = events.next().await {
+                if ev_tx.send(ev).is_err() {
+                    break;
                 }
             }
-            //
-            // let read_handle = async move {
-            //     read.for_each(|msg| async {
-            //         dbg!(&msg);
-            //         if let Some(msg) = process_msg(msg) {
-            //             ev_tx.send(msg).expect("failed to forward network message");
-            //         }
-            //     })
-            //     .await;
-            // };
-            //
-            // let write_handle = async move {
-            //     loop {
-            //         let req = from_handler_rx.recv();
-            //
-            //         match req {
-            //             Err(e) => {
-            //                 warn!("failed to forward message to sink: {}", e);
-            //             }
-            //             Ok(ev) => {
-            //                 if let Err(e) = write.send(ev).await {
-            //                     error!("failed to send message to server: {}", e);
-            //                 }
-            //             }
-            //         }
-            //     }
-            // };
-            //join!(read_handle, write_handle);
         };
-        self.handle = Some(self.rt.spawn(event_loop));
+        self.handle = Some(self.rt.spawn(forward));
         self.rx = Some(Arc::new(ev_rx));
-        self.tx = Some(Arc::new(from_handler_tx));
 
         Ok(())
     }
 
+    /// Close the connection, see [AsyncClient::disconnect]. The last event received with
+    /// [Self::try_recv] is [NetworkEvent::Disconnected]
+    pub fn disconnect(&mut self) {
+        // Don't hang the game if the server stopped answering
+        let inner = &mut self.inner;
+        let disconnect = async move { timeout(Duration::from_secs(5), inner.disconnect()).await };
+        let closed = self.rt.block_on(disconnect).is_ok();
+        if let Some(handle) = self.handle.take() {
+            if closed {
+                // The events are forwarded until the end
+                let _ = self.rt.block_on(handle);
+            } else {
+                warn!("timeout closing the connection");
+                handle.abort();
+            }
+        }
+    }
+
+    /// Decode the rows of the table `T` in `update`, using the schema registered in the
+    /// [BuildConnection] or the one of `T`
+    pub fn decode<T: TableRow>(
+        &self,
+        update: &TableUpdateJson,
+    ) -> Result<Vec<(TableOp, T)>, ClientError> {
+        self.inner.decode(update)
+    }
+
+    /// The local replica of the subscribed tables, updated before the messages are received
+    /// with [Self::try_recv]
+    pub fn cache(&self) -> MutexGuard<ClientCache> {
+        self.inner.cache()
+    }
+
+    /// Subscribe to the rows that match the SQL `queries`, see [AsyncClient::subscribe]
+    pub fn subscribe(&self, queries: Vec<String>) {
+        self.inner.subscribe(queries)
+    }
+
+    /// Run the SQL `query` against the database, see [AsyncClient::sql]
+    pub fn sql(&self, query: &str) -> Result<Vec<StmtResultJson>, ClientError> {
+        self.rt.block_on(self.inner.sql(query))
+    }
+
     pub fn try_recv(&self) -> Option<NetworkEvent> {
         if let Some(channel) = &self.rx {
             match channel.try_recv() {
@@ -210,19 +175,29 @@ impl Client {
         }
     }
 
+    /// Call the reducer `name` of the module with `args`. Poll the result of the call with
+    /// [ReducerCall::try_result]
+    pub fn call_reducer(
+        &self,
+        name: &str,
+        args: Vec<TypeValue>,
+    ) -> Result<ReducerCall, ClientError> {
+        self.inner.call_reducer(name, args)
+    }
+
     pub fn send_message(&self, msg: SpaceDbRequest) {
-        if let Some(msg) = serialize_msg(&self.con, msg) {
-            self.send_raw_message(msg);
-        }
+        self.inner.send_message(msg)
     }
 
     pub fn send_raw_message(&self, msg: tokio_tungstenite::tungstenite::Message) {
-        if let Some(channel) = &self.tx {
-            if let Err(e) = channel.send(msg) {
-                warn!("failed to forward message, sink: {:?}", e);
-            }
-        } else {
-            warn!("trying to send message with an uninitialized client",);
+        self.inner.send_raw_message(msg)
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        if self.handle.is_some() {
+            self.disconnect();
         }
     }
 }
@@ -230,28 +205,50 @@ impl Client {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tokio::time::{sleep, Duration};
+    use crate::mock::MockServer;
+    use std::time::Instant;
     use tungstenite::Message;
 
+    /// Poll the client until it receives an event
+    fn recv(client: &Client) -> NetworkEvent {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if let Some(ev) = client.try_recv() {
+                return ev;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("Timeout waiting for a network event");
+    }
+
     #[test]
     fn test_connect() {
-        let room_url =
-            "ws://127.0.0.1:3000/database/subscribe?name_or_address=extremeviolenceonspace";
-        info!("connecting to spacetimedb server: {:?}", room_url);
-
-        let mut client = Client::new().unwrap();
-        client.connect(room_url.into()).unwrap();
-
-        dbg!("Connected");
-        dbg!(client.is_running());
+        let rt = Runtime::new().unwrap();
+        let server = rt.block_on(MockServer::start()).unwrap();
+
+        let mut client = Client::new(&server.host(), "extremeviolenceonspace").unwrap();
+        client.connect().unwrap();
+        assert!(client.is_running());
+        assert_eq!(
+            client.identity().unwrap().identity,
+            server.identity().identity
+        );
+
+        assert!(matches!(recv(&client), NetworkEvent::Connected(_)));
+        assert!(matches!(
+            recv(&client),
+            NetworkEvent::Message(_, SpaceDbResponse::IdentityToken(_))
+        ));
 
         client.send_raw_message(Message::Text("Hi".to_string()));
-
-        let _ = client
-            .rt
-            .block_on(async { sleep(Duration::from_millis(100)).await });
-        for msg in client.try_recv() {
-            dbg!(msg);
+        let start = Instant::now();
+        while server.received().is_empty() && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
         }
+        assert_eq!(server.received(), vec![Message::Text("Hi".to_string())]);
+
+        client.disconnect();
+        assert!(!client.is_running());
+        assert!(matches!(recv(&client), NetworkEvent::Disconnected(_)));
     }
 }
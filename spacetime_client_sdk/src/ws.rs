@@ -1,13 +1,21 @@
-use crate::messages::IdentityTokenJson;
+use crate::errors::ClientError;
+use crate::messages::{IdentityTokenJson, TableSchemas};
+use anyhow::anyhow;
 use base64::prelude::BASE64_STANDARD;
 use base64::{engine::general_purpose, Engine as _};
 use hyper::http::request::Builder;
+use hyper::Body;
 use sha1::{Digest, Sha1};
+use spacetimedb::spacetimedb_lib::TupleDef;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_tungstenite::Connector;
 use tungstenite::http::header::{
-    AUTHORIZATION, CONNECTION, HOST, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY,
+    HeaderName, AUTHORIZATION, CONNECTION, HOST, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY,
     SEC_WEBSOCKET_PROTOCOL, SEC_WEBSOCKET_VERSION, UPGRADE,
 };
-use tungstenite::http::{Request, Uri};
+use tungstenite::http::{HeaderMap, HeaderValue, Request, Uri};
 
 const PROTO_WEBSOCKET: &str = "websocket";
 
@@ -17,11 +25,75 @@ pub enum Protocol {
     Binary,
 }
 
+/// How to reconnect when the connection is lost, with an exponential backoff
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// The delay is multiplied by this on each failed attempt
+    pub factor: u32,
+    /// Give up after this many attempts in a row. `None` retries forever
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            factor: 2,
+            max_attempts: Some(10),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Never reconnect
+    pub fn disabled() -> Self {
+        Self {
+            max_attempts: Some(0),
+            ..Default::default()
+        }
+    }
+
+    /// The delay before the reconnection `attempt`, starting at 1. `None` if should give up
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        if matches!(self.max_attempts, Some(max) if attempt > max) {
+            return None;
+        }
+        let factor = self.factor.saturating_pow(attempt.saturating_sub(1));
+        let delay = self.initial_delay.saturating_mul(factor);
+        Some(delay.min(self.max_delay))
+    }
+}
+
+/// How often to ping the server, and how long to wait for any message before the connection
+/// is considered dead
+#[derive(Debug, Clone)]
+pub struct HeartbeatPolicy {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for HeartbeatPolicy {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(15),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct BuildConnection {
     pub(crate) protocol: Protocol,
     pub(crate) auth: Option<IdentityTokenJson>,
     pub(crate) url: Uri,
+    pub(crate) schemas: Arc<TableSchemas>,
+    pub(crate) reconnect: ReconnectPolicy,
+    pub(crate) heartbeat: HeartbeatPolicy,
+    /// Sent with every request, eg: for a proxy in front of the server
+    pub(crate) headers: HeaderMap,
 }
 
 impl BuildConnection {
@@ -30,14 +102,83 @@ impl BuildConnection {
             protocol: Protocol::Text,
             auth: None,
             url,
+            schemas: Default::default(),
+            reconnect: Default::default(),
+            heartbeat: Default::default(),
+            headers: Default::default(),
+        }
+    }
+
+    /// Connect to the full websocket `url`, with the `ws://` or `wss://` scheme
+    pub fn from_url(url: &str) -> Result<Self, ClientError> {
+        let url = Uri::from_str(url)?;
+        match url.scheme_str() {
+            Some("ws" | "wss") => Ok(Self::new(url)),
+            _ => Err(anyhow!("expected a ws:// or wss:// url, got {url}").into()),
         }
     }
 
+    /// Connect to the database `name_or_address` of the server at `host`, eg: `127.0.0.1:3000`
+    /// or `wss://spacetime.example.com`. Without a scheme, `ws://` is used.
+    pub fn for_database(host: &str, name_or_address: &str) -> Result<Self, ClientError> {
+        let host = host.trim_end_matches('/');
+        let host = match host.split_once("://") {
+            Some(("http", rest)) => format!("ws://{rest}"),
+            Some(("https", rest)) => format!("wss://{rest}"),
+            Some(_) => host.to_string(),
+            None => format!("ws://{host}"),
+        };
+        Self::from_url(&format!(
+            "{host}/database/subscribe?name_or_address={name_or_address}"
+        ))
+    }
+
     pub fn with_auth(self, auth: IdentityTokenJson) -> Self {
         let mut x = self;
         x.auth = Some(auth);
         x
     }
+
+    /// Select the websocket sub-protocol. [Protocol::Binary] encodes the messages as protobuf
+    pub fn with_protocol(self, protocol: Protocol) -> Self {
+        let mut x = self;
+        x.protocol = protocol;
+        x
+    }
+
+    /// Register the schema of a table, needed to decode its rows with [Protocol::Binary]
+    pub fn with_table(self, table_name: &str, schema: TupleDef) -> Self {
+        let mut x = self;
+        Arc::make_mut(&mut x.schemas).insert(table_name.to_string(), schema);
+        x
+    }
+
+    pub fn with_reconnect(self, reconnect: ReconnectPolicy) -> Self {
+        let mut x = self;
+        x.reconnect = reconnect;
+        x
+    }
+
+    pub fn with_heartbeat(self, heartbeat: HeartbeatPolicy) -> Self {
+        let mut x = self;
+        x.heartbeat = heartbeat;
+        x
+    }
+
+    /// Add a header to the requests to the server
+    pub fn with_header(self, name: HeaderName, value: HeaderValue) -> Self {
+        let mut x = self;
+        x.headers.insert(name, value);
+        x
+    }
+
+    pub fn url(&self) -> &Uri {
+        &self.url
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
 }
 
 pub fn accept_key(key: &[u8]) -> String {
@@ -65,17 +206,133 @@ pub fn build_req(con: &BuildConnection) -> Builder {
         .header(SEC_WEBSOCKET_ACCEPT, accept_key(key.as_bytes()))
         .header(SEC_WEBSOCKET_KEY, key);
 
-    let b = if let Some(auth) = &con.auth {
+    let b = with_headers(b, con);
+
+    if let Some(host) = con.url.host() {
+        b.header(HOST, host)
+    } else {
+        b
+    }
+    .uri(&con.url)
+}
+
+/// Add the credentials & the extra headers of the connection
+fn with_headers(b: Builder, con: &BuildConnection) -> Builder {
+    let mut b = if let Some(auth) = &con.auth {
         let base64 = BASE64_STANDARD.encode(&format!("token:{}", auth.token));
         b.header(AUTHORIZATION, &format!("Basic {}", base64))
     } else {
         b
     };
+    for (name, value) in &con.headers {
+        b = b.header(name, value);
+    }
+    b
+}
 
-    if let Some(host) = con.url.host() {
-        b.header(HOST, host)
+/// The rustls connector for `wss://`, that trust the Mozilla root certificates
+pub fn tls_connector() -> Connector {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+            ta.subject,
+            ta.spki,
+            ta.name_constraints,
+        )
+    }));
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Connector::Rustls(Arc::new(config))
+}
+
+/// The url of the HTTP endpoint that run SQL queries against the database of the connection
+pub fn sql_url(con: &BuildConnection) -> Result<Uri, ClientError> {
+    let url = url::Url::parse(&con.url.to_string()).map_err(anyhow::Error::from)?;
+    let name_or_address = url
+        .query_pairs()
+        .find(|(k, _)| k == "name_or_address")
+        .map(|(_, v)| v.into_owned())
+        .ok_or_else(|| anyhow!("missing the database in {}", con.url))?;
+    let scheme = if url.scheme() == "wss" {
+        "https"
     } else {
-        b
+        "http"
+    };
+    let authority = con
+        .url
+        .authority()
+        .ok_or_else(|| anyhow!("missing the host in {}", con.url))?;
+
+    Ok(Uri::from_str(&format!(
+        "{scheme}://{authority}/database/sql/{name_or_address}"
+    ))?)
+}
+
+/// Build the request to run the SQL `query`, with the identity of the connection if any
+pub fn build_sql_req(con: &BuildConnection, query: &str) -> Result<Request<Body>, ClientError> {
+    let b = Request::builder().method("POST").uri(sql_url(con)?);
+    Ok(with_headers(b, con).body(Body::from(query.to_string()))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+            factor: 2,
+            max_attempts: Some(4),
+        };
+
+        assert_eq!(policy.delay(1), Some(Duration::from_secs(1)));
+        assert_eq!(policy.delay(2), Some(Duration::from_secs(2)));
+        assert_eq!(policy.delay(3), Some(Duration::from_secs(4)));
+        assert_eq!(policy.delay(4), Some(Duration::from_secs(5)));
+        assert_eq!(policy.delay(5), None);
+        assert_eq!(ReconnectPolicy::disabled().delay(1), None);
+    }
+
+    #[test]
+    fn test_sql_url() {
+        let url = "ws://127.0.0.1:3000/database/subscribe?name_or_address=extremeviolenceonspace";
+        let con = BuildConnection::new(Uri::from_str(url).unwrap());
+
+        assert_eq!(
+            sql_url(&con).unwrap(),
+            "http://127.0.0.1:3000/database/sql/extremeviolenceonspace"
+        );
+
+        let con = BuildConnection::new(Uri::from_static("ws://127.0.0.1:3000/database/subscribe"));
+        assert!(sql_url(&con).is_err());
+    }
+
+    #[test]
+    fn test_for_database() {
+        let con = BuildConnection::for_database("127.0.0.1:3000", "game").unwrap();
+        assert_eq!(
+            con.url(),
+            "ws://127.0.0.1:3000/database/subscribe?name_or_address=game"
+        );
+
+        let con = BuildConnection::for_database("https://spacetime.example.com/", "game")
+            .unwrap()
+            .with_header(
+                HeaderName::from_static("x-api-key"),
+                HeaderValue::from_static("secret"),
+            );
+        assert_eq!(
+            sql_url(&con).unwrap(),
+            "https://spacetime.example.com/database/sql/game"
+        );
+        let req = build_req(&con).body(()).unwrap();
+        assert_eq!(req.uri().scheme_str(), Some("wss"));
+        assert_eq!(req.headers()["x-api-key"], "secret");
+
+        assert!(BuildConnection::from_url("ftp://spacetime.example.com").is_err());
     }
-    .uri(&con.url)
 }
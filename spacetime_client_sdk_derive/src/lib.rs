@@ -0,0 +1,114 @@
+//! Derive macros for `spacetime_client_sdk`.
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Lit, Meta, NestedMeta};
+
+/// Implement `spacetime_client_sdk::table::TableRow` for a struct with named fields.
+///
+/// Each field is a column with the same name. The table name is the name of the struct,
+/// unless is overridden with `#[table_row(name = "...")]`.
+///
+/// Mark the unique column of the table with `#[primary_key]`, so the changes to a row are
+/// reported as updates instead of a delete and an insert.
+#[proc_macro_derive(TableRow, attributes(table_row, primary_key))]
+pub fn derive_table_row(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match table_row(&input) {
+        Ok(x) => x.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn table_name(input: &DeriveInput) -> syn::Result<String> {
+    let mut table_name = input.ident.to_string();
+
+    for attr in input.attrs.iter().filter(|x| x.path.is_ident("table_row")) {
+        if let Meta::List(list) = attr.parse_meta()? {
+            for nested in list.nested {
+                match nested {
+                    NestedMeta::Meta(Meta::NameValue(kv)) if kv.path.is_ident("name") => {
+                        match kv.lit {
+                            Lit::Str(name) => table_name = name.value(),
+                            lit => return Err(syn::Error::new_spanned(lit, "expected a string")),
+                        }
+                    }
+                    x => return Err(syn::Error::new_spanned(x, "expected `name = \"...\"`")),
+                }
+            }
+        }
+    }
+    Ok(table_name)
+}
+
+fn table_row(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let ident = &input.ident;
+    let table_name = table_name(input)?;
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    ident,
+                    "TableRow requires a struct with named fields",
+                ))
+            }
+        },
+        _ => return Err(syn::Error::new_spanned(ident, "TableRow requires a struct")),
+    };
+
+    let names: Vec<_> = fields.iter().map(|f| f.ident.as_ref().unwrap()).collect();
+    let columns: Vec<_> = names.iter().map(|x| x.to_string()).collect();
+    let types: Vec<_> = fields.iter().map(|f| &f.ty).collect();
+
+    let mut keys = fields
+        .iter()
+        .filter(|f| f.attrs.iter().any(|x| x.path.is_ident("primary_key")));
+    let primary_key = match (keys.next(), keys.next()) {
+        (None, _) => quote! {},
+        (Some(key), None) => {
+            let key = key.ident.as_ref().unwrap().to_string();
+            quote! {
+                fn primary_key() -> Option<&'static str> {
+                    Some(#key)
+                }
+            }
+        }
+        (Some(_), Some(x)) => {
+            return Err(syn::Error::new_spanned(
+                x,
+                "TableRow only supports one #[primary_key]",
+            ))
+        }
+    };
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::spacetime_client_sdk::table::TableRow for #ident #ty_generics #where_clause {
+            fn table_name() -> &'static str {
+                #table_name
+            }
+
+            #primary_key
+
+            fn schema() -> ::spacetime_client_sdk::spacetimedb::spacetimedb_lib::TupleDef {
+                ::spacetime_client_sdk::table::schema(
+                    #table_name,
+                    vec![
+                        #((#columns, <#types as ::spacetime_client_sdk::table::Column>::type_def()),)*
+                    ],
+                )
+            }
+
+            fn from_columns(
+                columns: &::spacetime_client_sdk::table::Columns,
+            ) -> Result<Self, ::spacetime_client_sdk::errors::ClientError> {
+                Ok(Self {
+                    #(#names: columns.get(#columns)?,)*
+                })
+            }
+        }
+    })
+}